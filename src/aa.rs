@@ -0,0 +1,119 @@
+use crate::target::RenderTarget;
+use rast::tint::*;
+
+// Supersampling anti aliasing. The frame is drawn into a target `scale` times the
+// size of the frame on each axis, so every pixel gets `scale * scale` samples,
+// which `resolve` averages down into the frame.
+pub struct Supersampler {
+    // Samples per pixel on each axis. 1 turns supersampling off.
+    pub scale: usize,
+    pub target: RenderTarget,
+    // Srgb to linear for every channel value, and linear back to Srgb at
+    // `LINEAR_STEPS` steps, so that samples are averaged in linear space.
+    to_linear: Vec<f32>,
+    to_srgb: Vec<u8>,
+}
+
+const LINEAR_STEPS: usize = 4096;
+
+impl Default for Supersampler {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Supersampler {
+    pub fn new(scale: usize) -> Self {
+        Self {
+            scale: scale.max(1),
+            target: RenderTarget::default(),
+            to_linear: Vec::new(),
+            to_srgb: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.scale * self.scale
+    }
+
+    /// Resizes the sample target for a `width` by `height` frame.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        // The tables are built on first use so that `default` stays cheap.
+        if self.to_linear.is_empty() {
+            self.to_linear = (0..256).map(|c| srgb_to_linear(c as f32 / 255.0)).collect();
+            self.to_srgb = (0..LINEAR_STEPS)
+                .map(|i| {
+                    let c = linear_to_srgb(i as f32 / (LINEAR_STEPS - 1) as f32);
+                    libm::roundf(c * 255.0) as u8
+                })
+                .collect();
+        }
+
+        self.scale = self.scale.max(1);
+        self.target.resize(width * self.scale, height * self.scale);
+    }
+
+    /// Averages the samples of each pixel into `frame_buffer`, and writes the
+    /// nearest sample depth of each pixel into `zbuffer` for the passes that run
+    /// after the resolve. Must be called after `resize`.
+    pub fn resolve(
+        &self,
+        frame_buffer: &mut [Srgb],
+        zbuffer: &mut [f32],
+        width: usize,
+        height: usize,
+    ) {
+        let scale = self.scale;
+        let sample_width = width * scale;
+        debug_assert_eq!(self.target.width, sample_width);
+        debug_assert_eq!(frame_buffer.len(), width * height);
+
+        let samples = self.samples() as f32;
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0f32; 4];
+                let mut depth = f32::MAX;
+                for sy in y * scale..(y + 1) * scale {
+                    for sx in x * scale..(x + 1) * scale {
+                        let index = sy * sample_width + sx;
+                        let sample = self.target.color[index];
+                        sum[0] += self.to_linear[sample.r as usize];
+                        sum[1] += self.to_linear[sample.g as usize];
+                        sum[2] += self.to_linear[sample.b as usize];
+                        sum[3] += sample.a as f32;
+                        depth = depth.min(self.target.depth[index]);
+                    }
+                }
+
+                let encode = |c: f32| {
+                    let step = (c / samples * (LINEAR_STEPS - 1) as f32) as usize;
+                    self.to_srgb[step.min(LINEAR_STEPS - 1)]
+                };
+                let index = y * width + x;
+                frame_buffer[index] = Srgb::new(
+                    encode(sum[0]),
+                    encode(sum[1]),
+                    encode(sum[2]),
+                    (sum[3] / samples) as u8,
+                );
+                zbuffer[index] = depth;
+            }
+        }
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        libm::powf((c + 0.055) / 1.055, 2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * libm::powf(c, 1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,172 @@
+use crate::math::*;
+
+// Joints are stored with parents before their children, so that joint transforms
+// can be resolved in a single pass.
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub parent: Option<usize>,
+    // Local transform in the rest pose.
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    // Model space to joint space in the bind pose.
+    pub inverse_bind_matrix: Mat4,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            translations: self.joints.iter().map(|joint| joint.translation).collect(),
+            rotations: self.joints.iter().map(|joint| joint.rotation).collect(),
+            scales: self.joints.iter().map(|joint| joint.scale).collect(),
+        }
+    }
+
+    /// Uses the rest pose as the bind pose.
+    pub fn compute_inverse_bind_matrices(&mut self) {
+        let mut matrices = Vec::new();
+        compute_joint_matrices(self, &self.rest_pose(), &mut matrices);
+        for (joint, matrix) in self.joints.iter_mut().zip(matrices) {
+            joint.inverse_bind_matrix = matrix.inverse_affine();
+        }
+    }
+}
+
+// Local joint transforms.
+#[derive(Debug, Clone, Default)]
+pub struct Pose {
+    pub translations: Vec<Vec3>,
+    pub rotations: Vec<Quat>,
+    pub scales: Vec<Vec3>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub joint: usize,
+    // Sorted keyframe times in seconds, one for each keyframe.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+// Drives the weight of one of the model's morph targets.
+#[derive(Debug, Clone)]
+pub struct MorphChannel {
+    pub target: usize,
+    // Sorted keyframe times in seconds, one for each weight.
+    pub times: Vec<f32>,
+    pub weights: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnimationClip {
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+    pub morph_channels: Vec<MorphChannel>,
+}
+
+/// Samples `clip` at `time` into `pose`. The clip loops, and joints without a
+/// channel are left in their rest pose.
+pub fn sample_clip(skeleton: &Skeleton, clip: &AnimationClip, time: f32, pose: &mut Pose) {
+    *pose = skeleton.rest_pose();
+    let time = if clip.duration > 0.0 {
+        time.rem_euclid(clip.duration)
+    } else {
+        0.0
+    };
+
+    for channel in clip.channels.iter() {
+        let Some((from, to, t)) = keyframe_interval(&channel.times, time) else {
+            continue;
+        };
+        match &channel.keyframes {
+            Keyframes::Translation(values) => {
+                pose.translations[channel.joint] = values[from].lerp(values[to], t);
+            }
+            Keyframes::Rotation(values) => {
+                pose.rotations[channel.joint] = values[from].slerp(values[to], t);
+            }
+            Keyframes::Scale(values) => {
+                pose.scales[channel.joint] = values[from].lerp(values[to], t);
+            }
+        }
+    }
+}
+
+/// Samples the morph target weights of `clip` at `time` into `weights`. The clip
+/// loops, and targets without a channel are left unchanged.
+pub fn sample_morph_weights(clip: &AnimationClip, time: f32, weights: &mut [f32]) {
+    let time = if clip.duration > 0.0 {
+        time.rem_euclid(clip.duration)
+    } else {
+        0.0
+    };
+
+    for channel in clip.morph_channels.iter() {
+        if let Some((from, to, t)) = keyframe_interval(&channel.times, time) {
+            let from = channel.weights[from];
+            let to = channel.weights[to];
+            weights[channel.target] = from + (to - from) * t;
+        }
+    }
+}
+
+// The keyframes surrounding `time` and the interpolation factor between them.
+// Times outside of the keyframes are clamped to the first or last keyframe.
+fn keyframe_interval(times: &[f32], time: f32) -> Option<(usize, usize, f32)> {
+    if times.is_empty() {
+        return None;
+    }
+
+    let next = times.partition_point(|t| *t <= time);
+    if next == 0 {
+        return Some((0, 0, 0.0));
+    }
+    if next == times.len() {
+        return Some((next - 1, next - 1, 0.0));
+    }
+
+    let from = next - 1;
+    let span = times[next] - times[from];
+    let t = if span > 0.0 {
+        (time - times[from]) / span
+    } else {
+        0.0
+    };
+    Some((from, next, t))
+}
+
+/// Model space transform of every joint in `pose`.
+pub fn compute_joint_matrices(skeleton: &Skeleton, pose: &Pose, out: &mut Vec<Mat4>) {
+    out.clear();
+    for (i, joint) in skeleton.joints.iter().enumerate() {
+        let local = compute_model_matrix(pose.translations[i], pose.rotations[i], pose.scales[i]);
+        let matrix = match joint.parent {
+            Some(parent) => {
+                debug_assert!(parent < i, "joint parents must come before their children");
+                out[parent].mult_mat4(&local)
+            }
+            None => local,
+        };
+        out.push(matrix);
+    }
+}
+
+/// Matrices that take a vertex from the bind pose to `pose`, indexed by joint.
+pub fn compute_skinning_matrices(skeleton: &Skeleton, pose: &Pose, out: &mut Vec<Mat4>) {
+    compute_joint_matrices(skeleton, pose, out);
+    for (matrix, joint) in out.iter_mut().zip(skeleton.joints.iter()) {
+        *matrix = matrix.mult_mat4(&joint.inverse_bind_matrix);
+    }
+}
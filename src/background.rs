@@ -0,0 +1,174 @@
+use crate::{camera::Camera, math::*, raster};
+use rast::tint::*;
+
+pub enum Background {
+    Color(Srgb),
+    // Blends from `horizon` up to `zenith` above the horizon, and down to `ground`
+    // below it.
+    Gradient {
+        zenith: Srgb,
+        horizon: Srgb,
+        ground: Srgb,
+    },
+    // Stars are placed on a cube around the camera, so they stay put as the camera
+    // turns. `density` is the fraction of cells that have a star.
+    Starfield {
+        color: Srgb,
+        density: f32,
+        seed: u32,
+    },
+    // Faces are ordered +x, -x, +y, -y, +z, -z.
+    Cubemap([(usize, usize, Vec<Srgb>); 6]),
+    Equirectangular((usize, usize, Vec<Srgb>)),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Color(Srgb::from_rgb(82, 82, 82))
+    }
+}
+
+/// Draws `background` into the pixels that have no depth, in the direction the
+/// camera looks through each pixel.
+pub fn draw_background(
+    background: &Background,
+    frame_buffer: &mut [Srgb],
+    zbuffer: &[f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+) {
+    debug_assert_eq!(frame_buffer.len(), zbuffer.len());
+
+    if let Background::Color(color) = background {
+        for (pixel, depth) in frame_buffer.iter_mut().zip(zbuffer.iter()) {
+            if *depth >= 1.0 {
+                *pixel = *color;
+            }
+        }
+        return;
+    }
+
+    let (right, up, forward) = view_basis(camera, width, height);
+    for y in 0..height {
+        let ndc_y = 1.0 - (y as f32 + 0.5) / height as f32 * 2.0;
+        for x in 0..width {
+            let index = y * width + x;
+            if zbuffer[index] < 1.0 {
+                continue;
+            }
+            let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let dir = (forward + right * ndc_x + up * ndc_y).normalize();
+            frame_buffer[index] = sample_background(background, dir);
+        }
+    }
+}
+
+/// Color of `background` in the world space direction `dir`.
+pub fn sample_background(background: &Background, dir: Vec3) -> Srgb {
+    match background {
+        Background::Color(color) => *color,
+        Background::Gradient {
+            zenith,
+            horizon,
+            ground,
+        } => {
+            if dir.y >= 0.0 {
+                raster::lerp_srgb(*horizon, *zenith, libm::sqrtf(dir.y))
+            } else {
+                raster::lerp_srgb(*horizon, *ground, libm::sqrtf(-dir.y))
+            }
+        }
+        Background::Starfield {
+            color,
+            density,
+            seed,
+        } => {
+            const CELLS: f32 = 256.0;
+            const RADIUS: f32 = 0.3;
+
+            let (face, st) = cube_face(dir);
+            let cell = Vec2::new(libm::floorf(st.x * CELLS), libm::floorf(st.y * CELLS));
+            let hash = |i: u32| {
+                [face as u32, cell.x as u32, cell.y as u32, i]
+                    .into_iter()
+                    .fold(*seed, |hash, x| hash_u32(hash ^ x))
+            };
+            let unit = |i: u32| hash(i) as f32 / u32::MAX as f32;
+
+            if unit(0) >= *density {
+                return Srgb::from_rgb(0, 0, 0);
+            }
+            // Somewhere in the middle of the cell, so that stars don't touch.
+            let star = cell + Vec2::new(0.25 + unit(1) * 0.5, 0.25 + unit(2) * 0.5);
+            let dist = (st * CELLS - star).length();
+            if dist >= RADIUS {
+                return Srgb::from_rgb(0, 0, 0);
+            }
+            let brightness = 0.3 + unit(3) * 0.7;
+            raster::scale_srgb(*color, brightness * (1.0 - dist / RADIUS))
+        }
+        Background::Cubemap(faces) => {
+            let (face, st) = cube_face(dir);
+            // Cube faces are addressed from the top, uvs from the bottom.
+            raster::sample_texture(&faces[face], Vec2::new(st.x, 1.0 - st.y), true)
+        }
+        Background::Equirectangular(texture) => {
+            let u = libm::atan2f(dir.x, -dir.z) / core::f32::consts::TAU + 0.5;
+            let v = libm::acosf(dir.y.clamp(-1.0, 1.0)) / core::f32::consts::PI;
+            raster::sample_texture(texture, Vec2::new(u, 1.0 - v), true)
+        }
+    }
+}
+
+// World space camera axes, scaled so that `forward + right * x + up * y` points
+// through the pixel at normalized device coordinates `x` and `y`.
+fn view_basis(camera: &Camera, width: usize, height: usize) -> (Vec3, Vec3, Vec3) {
+    let inverse =
+        compute_view_matrix(camera.translation, camera.yaw, camera.pitch).inverse_affine();
+    let tan = libm::tanf(camera.fov / 2.0);
+    let aspect = width as f32 / height as f32;
+    let axis = |v: Vec3| inverse.mult_vec4(v.extend(0.0)).reduce();
+    (
+        axis(Vec3::x(tan * aspect)),
+        axis(Vec3::y(tan)),
+        axis(Vec3::z(-1.0)),
+    )
+}
+
+// The cube face `dir` points through, and the coordinates on that face from the
+// top left, following the OpenGL cubemap layout.
+fn cube_face(dir: Vec3) -> (usize, Vec2) {
+    let (ax, ay, az) = (libm::fabsf(dir.x), libm::fabsf(dir.y), libm::fabsf(dir.z));
+    let (face, major, s, t) = if ax >= ay && ax >= az {
+        if dir.x > 0.0 {
+            (0, ax, -dir.z, -dir.y)
+        } else {
+            (1, ax, dir.z, -dir.y)
+        }
+    } else if ay >= az {
+        if dir.y > 0.0 {
+            (2, ay, dir.x, dir.z)
+        } else {
+            (3, ay, dir.x, -dir.z)
+        }
+    } else if dir.z > 0.0 {
+        (4, az, dir.x, -dir.y)
+    } else {
+        (5, az, -dir.x, -dir.y)
+    };
+    (
+        face,
+        Vec2::new((s / major + 1.0) / 2.0, (t / major + 1.0) / 2.0),
+    )
+}
+
+// https://nullprogram.com/blog/2018/07/31/
+fn hash_u32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
@@ -1,48 +1,255 @@
 use crate::Memory;
+use crate::animation::{
+    self, AnimationClip, Channel, Joint, Keyframes, MorphChannel, Pose, Skeleton,
+};
 use crate::camera::Camera;
+use crate::hiz::DepthPyramid;
 use crate::io;
 use crate::math::*;
 use crate::model;
+use crate::scene::{NodeId, Scene};
+use crate::shadow::{Light, LightKind, ShadowMap};
 use rast::tint::*;
 
 pub struct BlenderMemory {
-    teapot: model::Model,
-    ibuki_obb: model::Obb,
-    ibuki: model::Model,
+    scene: Scene,
+    ibuki: usize,
+    ibuki_node: NodeId,
+    sway: AnimationClip,
+    pose: Pose,
+    skinning_matrices: Vec<Mat4>,
+    time: f32,
+    teapot: usize,
+    squash: AnimationClip,
+    morph_weights: Vec<f32>,
+    pivot: NodeId,
+    teapots: [NodeId; 3],
+    light: Light,
+    shadow_map: ShadowMap,
+    depth_pyramid: DepthPyramid,
     angle: f32,
 }
 
 impl Default for BlenderMemory {
     fn default() -> Self {
-        let materials = [
-            ("assets/ibuki/bloomers.bin", "bloomers"),
-            ("assets/ibuki/coat.bin", "coat"),
-            ("assets/ibuki/face.bin", "face"),
-            ("assets/ibuki/halo.bin", "halo"),
-            ("assets/ibuki/package.bin", "package"),
-            ("assets/ibuki/body.bin", "body"),
-            ("assets/ibuki/eye.bin", "eye"),
-            ("assets/ibuki/hair.bin", "hair"),
-            ("assets/ibuki/shirt.bin", "shirt"),
-        ]
-        .into_iter()
-        .map(|(path, name)| io::debug_image_file(path).map(|img| (name.to_string(), img)))
-        .collect::<Option<Vec<_>>>()
-        .expect("failed to load ibuki materials");
-
-        let ibuki = io::debug_obj_file("assets/ibuki/ibuki.obj", materials)
+        let mut ibuki = io::debug_obj_file("assets/ibuki/ibuki.obj", ibuki_materials())
             .expect("could not load `ibuki.obj`");
+        let sway = rig_sway(&mut ibuki);
+        let mut teapot = io::debug_obj_file("assets/teapot.obj", Vec::new())
+            .expect("could not load `teapot.obj`");
+        let squash = morph_squash(&mut teapot);
+
+        let mut scene = Scene::default();
+        let ibuki = scene.add_model_with_lods(ibuki, &[(0.5, 0.4), (0.2, 0.15)]);
+        let teapot = scene.add_model_with_lods(teapot, &[(0.5, 0.2), (0.15, 0.05)]);
+
+        // Ibuki and the ground hide the teapots when they pass behind or below.
+        let ibuki_node = scene.add_node(None);
+        scene.node_mut(ibuki_node).model = Some(ibuki);
+        scene.node_mut(ibuki_node).occluder = true;
+
+        let ground = scene.add_model(ground_plane(100.0));
+        let ground_node = scene.add_node(None);
+        scene.node_mut(ground_node).model = Some(ground);
+        scene.node_mut(ground_node).occluder = true;
+
+        // The teapots orbit around a shared pivot.
+        let pivot = scene.add_node(None);
+        scene.node_mut(pivot).translation = Vec3::x(50.0);
+        let teapots = core::array::from_fn(|i| {
+            let node = scene.add_node(Some(pivot));
+            let node_mut = scene.node_mut(node);
+            node_mut.translation = Vec3::x((i as f32 - 1.0) * 10.0);
+            node_mut.model = Some(teapot);
+            node
+        });
+        scene.update();
 
         Self {
-            teapot: io::debug_obj_file("assets/teapot.obj", Vec::new())
-                .expect("could not load `teapot.obj`"),
-            ibuki_obb: model::compute_obb(&ibuki),
+            scene,
             ibuki,
+            ibuki_node,
+            sway,
+            pose: Pose::default(),
+            skinning_matrices: Vec::new(),
+            time: 0.0,
+            teapot,
+            squash,
+            morph_weights: vec![0.0],
+            pivot,
+            teapots,
+            light: Light {
+                kind: LightKind::Directional { half_extent: 100.0 },
+                translation: Vec3::new(-100.0, 150.0, -100.0),
+                pitch: 45f32.to_radians(),
+                yaw: 45f32.to_radians(),
+                nearz: 1.0,
+                farz: 500.0,
+            },
+            shadow_map: ShadowMap::new(1024, 1024),
+            depth_pyramid: DepthPyramid::default(),
             angle: 0.0,
         }
     }
 }
 
+pub fn ibuki_materials() -> Vec<(String, model::Material)> {
+    [
+        ("assets/ibuki/bloomers.bin", "bloomers"),
+        ("assets/ibuki/coat.bin", "coat"),
+        ("assets/ibuki/face.bin", "face"),
+        ("assets/ibuki/halo.bin", "halo"),
+        ("assets/ibuki/package.bin", "package"),
+        ("assets/ibuki/body.bin", "body"),
+        ("assets/ibuki/eye.bin", "eye"),
+        ("assets/ibuki/hair.bin", "hair"),
+        ("assets/ibuki/shirt.bin", "shirt"),
+    ]
+    .into_iter()
+    .map(|(path, name)| {
+        io::debug_image_file(path).map(|img| {
+            let mut material = model::Material::textured(img);
+            if name == "halo" {
+                material.blend_mode = rast::BlendMode::Alpha;
+            }
+            (name.to_string(), material)
+        })
+    })
+    .collect::<Option<Vec<_>>>()
+    .expect("failed to load ibuki materials")
+}
+
+// `ibuki.obj` has no rig, so the model gets a two joint skeleton that bends at
+// the waist, with a clip that sways the upper body.
+fn rig_sway(model: &mut model::Model) -> AnimationClip {
+    let obb = model::compute_obb(model);
+    let center = (obb.min + obb.max) / 2.0;
+    let height = obb.max.y - obb.min.y;
+    let waist = height * 0.5;
+
+    let mut skeleton = Skeleton {
+        joints: vec![
+            Joint {
+                parent: None,
+                translation: Vec3::new(center.x, obb.min.y, center.z),
+                rotation: Quat::IDENTITY,
+                scale: Vec3::splat(1.0),
+                inverse_bind_matrix: Mat4::IDENTITY,
+            },
+            Joint {
+                parent: Some(0),
+                translation: Vec3::y(waist),
+                rotation: Quat::IDENTITY,
+                scale: Vec3::splat(1.0),
+                inverse_bind_matrix: Mat4::IDENTITY,
+            },
+        ],
+    };
+    skeleton.compute_inverse_bind_matrices();
+
+    // Blend between the joints around the waist.
+    let blend = height * 0.1;
+    model.joints = vec![[0, 1, 0, 0]; model.verts.len()];
+    model.weights = model
+        .verts
+        .iter()
+        .map(|v| {
+            let upper = ((v.y - obb.min.y - waist + blend) / (blend * 2.0)).clamp(0.0, 1.0);
+            [1.0 - upper, upper, 0.0, 0.0]
+        })
+        .collect();
+    model.skeleton = Some(skeleton);
+
+    AnimationClip {
+        duration: 2.0,
+        channels: vec![Channel {
+            joint: 1,
+            times: vec![0.0, 1.0, 2.0],
+            keyframes: Keyframes::Rotation(vec![
+                Quat::from_rotation_z(-0.15),
+                Quat::from_rotation_z(0.15),
+                Quat::from_rotation_z(-0.15),
+            ]),
+        }],
+        ..Default::default()
+    }
+}
+
+// Squash and stretch morph target for the teapot, flattening it around its base,
+// with a clip that bounces between the two.
+fn morph_squash(model: &mut model::Model) -> AnimationClip {
+    let obb = model::compute_obb(model);
+    let base = obb.min.y;
+    model.morph_targets = vec![model::MorphTarget {
+        name: "squash".to_string(),
+        position_deltas: model
+            .verts
+            .iter()
+            .map(|v| Vec3::new(v.x * 0.3, (base - v.y) * 0.4, v.z * 0.3))
+            .collect(),
+        normal_deltas: Vec::new(),
+    }];
+
+    AnimationClip {
+        duration: 1.0,
+        morph_channels: vec![MorphChannel {
+            target: 0,
+            times: vec![0.0, 0.5, 1.0],
+            weights: vec![0.0, 1.0, 0.0],
+        }],
+        ..Default::default()
+    }
+}
+
+fn ground_plane(half_extent: f32) -> model::Model {
+    model::Model {
+        faces: vec![0, 1, 2, 0, 2, 3],
+        face_materials: vec![0, 0],
+        verts: vec![
+            Vec3::new(-half_extent, 0.0, -half_extent),
+            Vec3::new(half_extent, 0.0, -half_extent),
+            Vec3::new(half_extent, 0.0, half_extent),
+            Vec3::new(-half_extent, 0.0, half_extent),
+        ],
+        materials: vec![model::Material {
+            base_color: LinearRgb::from_rgb(0.4, 0.4, 0.4),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+/// The nearest face in the demo scene hit by `ray`.
+pub fn pick(memory: &BlenderMemory, ray: Ray) -> Option<crate::scene::Hit> {
+    crate::scene::pick(&memory.scene, ray)
+}
+
+/// Number of nodes hidden by occlusion culling in the last frame.
+pub fn occluded(memory: &BlenderMemory) -> usize {
+    memory.depth_pyramid.culled
+}
+
+/// Path traces the demo scene as it was last rendered, lit by the same light.
+pub fn trace(
+    memory: &BlenderMemory,
+    tracer: &mut crate::trace::Tracer,
+    background: &crate::background::Background,
+    frame_buffer: &mut [Srgb],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+) {
+    tracer.render(
+        &memory.scene,
+        &memory.light,
+        background,
+        frame_buffer,
+        width,
+        height,
+        camera,
+    );
+}
+
 pub fn render(
     memory: &mut BlenderMemory,
     frame_buffer: &mut [Srgb],
@@ -51,85 +258,84 @@ pub fn render(
     width: usize,
     height: usize,
     delta: f32,
+    debug_view: model::DebugView,
 ) {
     memory.angle = (memory.angle + delta) % core::f32::consts::TAU;
 
-    let obb = memory.ibuki_obb;
-    let translation = Vec3::ZERO;
-    let scale = Vec3::splat(1.0);
-    let rotation = Quat::default();
-    let pyr = Vec3::ZERO;
-    // let pyr = Vec3::new(0.0, memory.angle, 0.0);
-    if model::obb_visible(width, height, camera, obb, translation, pyr) {
-        let (dur, _) = glazer::debug_time_millis(|| {
-            // let (dur, model_matrix) =
-            //     glazer::debug_time_nanos(|| compute_model_matrix(translation, rotation, scale));
-            // glazer::log!("  compute matrix: {dur}ns");
-            let model_matrix = compute_model_matrix(translation, rotation, scale);
-            model::draw_model_matrix(
-                frame_buffer,
-                zbuffer,
-                width,
-                height,
-                camera,
-                &memory.ibuki,
-                model_matrix,
-            );
-        });
-        // glazer::log!("matrix: {dur}ms");
-
-        // let (dur, _) = glazer::debug_time_millis(|| {
-        //     model::draw_model(
-        //         frame_buffer,
-        //         zbuffer,
-        //         width,
-        //         height,
-        //         camera,
-        //         &memory.ibuki,
-        //         translation,
-        //         pyr,
-        //     );
-        // });
-        // glazer::log!("manual: {dur}ms");
-
-        // model::debug_draw_obb(
-        //     frame_buffer,
-        //     zbuffer,
-        //     width,
-        //     height,
-        //     camera,
-        //     obb,
-        //     translation,
-        //     pyr,
-        //     Srgb::rgb(0, 255, 0),
-        // );
+    let angle = memory.angle;
+    memory.scene.node_mut(memory.pivot).rotation = Quat::from_rotation_y(angle);
+    for teapot in memory.teapots {
+        memory.scene.node_mut(teapot).rotation =
+            Quat::from_pitch_yaw_roll(Vec3::new(angle, angle, angle));
     }
+    memory.scene.update();
 
-    let obb = model::compute_obb(&memory.teapot);
-    for x in -1..=1 {
-        let translation = Vec3::x(x as f32 * 10.0 + 50.0);
-        let pyr = Vec3::new(memory.angle, memory.angle, memory.angle);
-        if model::obb_visible(width, height, camera, obb, translation, pyr) {
-            model::draw_model(
-                frame_buffer,
-                zbuffer,
-                width,
-                height,
-                camera,
-                &memory.teapot,
-                translation,
-                pyr,
-            );
+    memory.time += delta;
+    // Skin the level of detail ibuki is drawn with.
+    let ibuki = memory
+        .scene
+        .lod_model(memory.ibuki, memory.ibuki_node, camera);
+    let node = memory.scene.node_mut(memory.ibuki_node);
+    node.model = Some(ibuki);
+    let mut verts = node.deformed_verts.take().unwrap_or_default();
+    let ibuki = memory.scene.model(ibuki);
+    let skeleton = ibuki.skeleton.as_ref().unwrap();
+    animation::sample_clip(skeleton, &memory.sway, memory.time, &mut memory.pose);
+    animation::compute_skinning_matrices(skeleton, &memory.pose, &mut memory.skinning_matrices);
+    verts.clear();
+    verts.extend_from_slice(&ibuki.verts);
+    model::skin_vertices(ibuki, &memory.skinning_matrices, &mut verts);
+    memory.scene.node_mut(memory.ibuki_node).deformed_verts = Some(verts);
+    memory.scene.refit_deformed(memory.ibuki_node);
+
+    // Only the middle teapot is squashed, the others keep drawing the shared mesh.
+    let mut verts = memory
+        .scene
+        .node_mut(memory.teapots[1])
+        .deformed_verts
+        .take()
+        .unwrap_or_default();
+    let teapot = memory.scene.model(memory.teapot);
+    animation::sample_morph_weights(&memory.squash, memory.time, &mut memory.morph_weights);
+    verts.clear();
+    verts.extend_from_slice(&teapot.verts);
+    model::morph_vertices(teapot, &memory.morph_weights, &mut verts, &mut []);
+    memory.scene.node_mut(memory.teapots[1]).deformed_verts = Some(verts);
+    memory.scene.refit_deformed(memory.teapots[1]);
 
-            model::debug_draw_obb(
+    let (dur, _) = glazer::debug_time_millis(|| {
+        memory.shadow_map.begin(&memory.light);
+        crate::scene::render_shadow_map(&memory.scene, &mut memory.shadow_map);
+    });
+    // glazer::log!("shadow map: {dur}ms");
+
+    let (dur, _) = glazer::debug_time_millis(|| {
+        crate::scene::render(
+            &mut memory.scene,
+            frame_buffer,
+            zbuffer,
+            camera,
+            width,
+            height,
+            Some(&memory.shadow_map),
+            Some(&mut memory.depth_pyramid),
+            debug_view,
+        );
+    });
+    // glazer::log!("scene: {dur}ms, {} occluded", memory.depth_pyramid.culled);
+
+    for teapot in memory.teapots {
+        let model_matrix = memory.scene.world_matrix(teapot);
+        let obb = memory.scene.node_obb(teapot);
+        if model::obb_visible_matrix(width, height, camera, obb, model_matrix) {
+            model::debug_draw_obb_matrix(
                 frame_buffer,
                 zbuffer,
                 width,
                 height,
                 camera,
                 obb,
-                translation,
-                pyr,
+                model_matrix,
                 Srgb::from_rgb(0, 255, 0),
             );
         }
@@ -1,4 +1,8 @@
-use crate::{camera::Camera, math::*};
+use crate::{
+    camera::Camera,
+    math::*,
+    model::{self, DebugView, DrawPass, Model, Obb},
+};
 use rast::tint::*;
 
 const BOUNDS: f32 = 240.0;
@@ -10,7 +14,10 @@ const MIN_SPEED_SQ: f32 = MIN_SPEED * MIN_SPEED;
 
 pub struct BoidMemory {
     boids: [Boid; BOID_COUNT],
-    vertices: [Vec3; 36],
+    cube: Model,
+    cube_obb: Obb,
+    instances: Vec<Mat4>,
+    tints: Vec<LinearRgb>,
     //
     margin: f32,
     turn_factor: f32,
@@ -25,6 +32,11 @@ pub struct BoidMemory {
 
 impl Default for BoidMemory {
     fn default() -> Self {
+        let cube = Model {
+            faces: (0..36).collect(),
+            verts: boid_vertices().to_vec(),
+            ..Default::default()
+        };
         BoidMemory {
             boids: core::array::from_fn(|i| Boid {
                 translation: Vec3::new(
@@ -46,7 +58,10 @@ impl Default for BoidMemory {
                     * MAX_SPEED
                     - MAX_SPEED,
             }),
-            vertices: boid_vertices(),
+            cube_obb: model::compute_obb(&cube),
+            cube,
+            instances: Vec::with_capacity(BOID_COUNT),
+            tints: Vec::with_capacity(BOID_COUNT),
             //
             turn_factor: 1.0,
             margin: BOUNDS / 4.0,
@@ -74,8 +89,17 @@ pub fn render(
     width: usize,
     height: usize,
     camera: &Camera,
+    debug_view: DebugView,
 ) {
-    draw_boids(memory, frame_buffer, zbuffer, width, height, camera);
+    draw_boids(
+        memory,
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        debug_view,
+    );
     draw_bounds(frame_buffer, zbuffer, width, height, camera);
 }
 
@@ -168,45 +192,46 @@ fn boid_forces(memory: &mut BoidMemory) {
 }
 
 fn draw_boids(
-    memory: &BoidMemory,
+    memory: &mut BoidMemory,
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
     width: usize,
     height: usize,
     camera: &Camera,
+    debug_view: DebugView,
 ) {
+    memory.instances.clear();
+    memory.tints.clear();
     for boid in memory.boids.iter() {
-        for face in memory.vertices.chunks(3) {
-            let v1 = face[0] + boid.translation;
-            let v2 = face[1] + boid.translation;
-            let v3 = face[2] + boid.translation;
+        memory.instances.push(compute_model_matrix(
+            boid.translation,
+            Quat::IDENTITY,
+            Vec3::splat(1.0),
+        ));
 
-            if let Some((v1, v2, v3)) = crate::math::triangle_world_to_screen_space_clipped(
-                width, height, camera, v1, v2, v3,
-            ) {
-                let r = (boid.translation.x.clamp(-BOUNDS, BOUNDS) / BOUNDS + 1.0) / 2.0;
-                let g = (boid.translation.y.clamp(-BOUNDS, BOUNDS) / BOUNDS + 1.0) / 2.0;
-                let b = (boid.translation.z.clamp(-BOUNDS, BOUNDS) / BOUNDS + 1.0) / 2.0;
-                let color = LinearRgb::from_rgb(r, g, b).to_srgb();
-                rast::rast_triangle_colored_checked(
-                    frame_buffer,
-                    zbuffer,
-                    width,
-                    height,
-                    libm::floorf(v1.x) as i32,
-                    libm::floorf(v1.y) as i32,
-                    v1.z,
-                    libm::floorf(v2.x) as i32,
-                    libm::floorf(v2.y) as i32,
-                    v2.z,
-                    libm::floorf(v3.x) as i32,
-                    libm::floorf(v3.y) as i32,
-                    v3.z,
-                    color,
-                );
-            }
-        }
+        let r = (boid.translation.x.clamp(-BOUNDS, BOUNDS) / BOUNDS + 1.0) / 2.0;
+        let g = (boid.translation.y.clamp(-BOUNDS, BOUNDS) / BOUNDS + 1.0) / 2.0;
+        let b = (boid.translation.z.clamp(-BOUNDS, BOUNDS) / BOUNDS + 1.0) / 2.0;
+        memory.tints.push(LinearRgb::from_rgb(r, g, b));
     }
+
+    let mut pass = DrawPass {
+        debug_view,
+        ..Default::default()
+    };
+    crate::model::draw_model_instanced(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        &memory.cube,
+        memory.cube_obb,
+        &memory.instances,
+        Some(&memory.tints),
+        Some(&mut pass),
+    );
+    pass.flush(frame_buffer, zbuffer, width, height);
 }
 
 fn draw_bounds(
@@ -216,6 +241,8 @@ fn draw_bounds(
     height: usize,
     camera: &Camera,
 ) {
+    // Same depth as the instanced cubes.
+    let view_proj_matrix = crate::hiz::view_proj_matrix(camera, width, height);
     let corners = cube(Vec3::new(BOUNDS, BOUNDS, BOUNDS));
     #[rustfmt::skip]
     let edges = [
@@ -225,25 +252,16 @@ fn draw_bounds(
         (1, 5), (2, 6), (3, 7),
     ];
     for (i1, i2) in edges.into_iter() {
-        let v1 =
-            crate::math::vertex_world_to_screen_space_clipped(width, height, camera, corners[i1]);
-        let v2 =
-            crate::math::vertex_world_to_screen_space_clipped(width, height, camera, corners[i2]);
-        if let (Some(v1), Some(v2)) = (v1, v2) {
-            rast::rast_line_checked(
-                frame_buffer,
-                zbuffer,
-                width,
-                height,
-                libm::floorf(v1.x) as i32,
-                libm::floorf(v1.y) as i32,
-                v1.z,
-                libm::floorf(v2.x) as i32,
-                libm::floorf(v2.y) as i32,
-                v2.z,
-                Srgb::from_rgb(0, 255, 0),
-            );
-        }
+        crate::raster::rast_line_matrix(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            &view_proj_matrix,
+            corners[i1],
+            corners[i2],
+            Srgb::from_rgb(0, 255, 0),
+        );
     }
 }
 
@@ -0,0 +1,666 @@
+use crate::math::*;
+
+// Bounding volume hierarchy over the triangles of a model, built with binned
+// surface area heuristic splits.
+//
+// The hierarchy only stores triangle indices, so the same faces and vertices it
+// was built from must be passed to every query. After the vertices move, e.g.
+// after skinning, `refit` updates the bounds without changing the hierarchy,
+// which stays correct but gets slower the further the vertices move.
+#[derive(Debug, Default, Clone)]
+pub struct Bvh {
+    // The root is the first node. Children come after their parent.
+    pub nodes: Vec<BvhNode>,
+    // Triangle indices, ordered so that each leaf covers a contiguous range.
+    pub triangles: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BvhNode {
+    pub min: Vec3,
+    pub max: Vec3,
+    // For leaves, the range `start..start + count` of `Bvh::triangles`. For
+    // interior nodes, `count` is 0 and the children are at `start` and
+    // `start + 1`.
+    pub start: usize,
+    pub count: usize,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// Cost of visiting a node relative to testing a triangle.
+const TRAVERSAL_COST: f32 = 1.0;
+const MAX_DEPTH: usize = 64;
+
+impl Bvh {
+    /// Builds a hierarchy over the triangles in `faces`, three vertex indices per
+    /// triangle.
+    pub fn new(faces: &[usize], verts: &[Vec3]) -> Self {
+        let triangle_count = faces.len() / 3;
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(triangle_count * 2),
+            triangles: (0..triangle_count).collect(),
+        };
+        if triangle_count == 0 {
+            return bvh;
+        }
+
+        // Centroid and bounds of every triangle.
+        let triangle_bounds = (0..triangle_count)
+            .map(|triangle| {
+                let [v1, v2, v3] = triangle_verts(faces, verts, triangle);
+                (
+                    (v1 + v2 + v3) / 3.0,
+                    min_vec3(min_vec3(v1, v2), v3),
+                    max_vec3(max_vec3(v1, v2), v3),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        bvh.nodes.push(BvhNode {
+            min: Vec3::ZERO,
+            max: Vec3::ZERO,
+            start: 0,
+            count: triangle_count,
+        });
+        bvh.subdivide(0, faces, verts, &triangle_bounds, 0);
+        bvh
+    }
+
+    /// Recomputes every bound for the moved `verts`.
+    pub fn refit(&mut self, faces: &[usize], verts: &[Vec3]) {
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            let (min, max) = if node.is_leaf() {
+                self.leaf_bounds(node.start, node.count, faces, verts)
+            } else {
+                let (left, right) = (self.nodes[node.start], self.nodes[node.start + 1]);
+                (min_vec3(left.min, right.min), max_vec3(left.max, right.max))
+            };
+            self.nodes[i].min = min;
+            self.nodes[i].max = max;
+        }
+    }
+
+    /// The nearest triangle hit by `ray` closer than `max_t`, with the distance
+    /// along the ray and the barycentric weights of the hit.
+    pub fn closest_hit(
+        &self,
+        faces: &[usize],
+        verts: &[Vec3],
+        ray: &Ray,
+        max_t: f32,
+    ) -> Option<(usize, f32, Vec3)> {
+        let mut nearest = None;
+        let mut max_t = max_t;
+        self.traverse_ray(ray, &mut max_t, |triangle, max_t| {
+            let [v1, v2, v3] = triangle_verts(faces, verts, triangle);
+            let hit = ray_triangle(ray, v1, v2, v3).filter(|(t, _)| *t < *max_t);
+            if let Some((t, barycentrics)) = hit {
+                *max_t = t;
+                nearest = Some((triangle, t, barycentrics));
+            }
+            false
+        });
+        nearest
+    }
+
+    /// Whether `ray` hits any triangle closer than `max_t`, e.g. for shadow rays.
+    pub fn any_hit(&self, faces: &[usize], verts: &[Vec3], ray: &Ray, max_t: f32) -> bool {
+        let mut hit = false;
+        let mut max_t = max_t;
+        self.traverse_ray(ray, &mut max_t, |triangle, max_t| {
+            let [v1, v2, v3] = triangle_verts(faces, verts, triangle);
+            hit = ray_triangle(ray, v1, v2, v3).is_some_and(|(t, _)| t < *max_t);
+            hit
+        });
+        hit
+    }
+
+    /// Appends every triangle that overlaps the box from `min` to `max` to
+    /// `overlapping`.
+    pub fn overlap_box(
+        &self,
+        faces: &[usize],
+        verts: &[Vec3],
+        min: Vec3,
+        max: Vec3,
+        overlapping: &mut Vec<usize>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let center = (min + max) / 2.0;
+        let half_extent = (max - min) / 2.0;
+        let mut stack = [0; MAX_DEPTH];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = self.nodes[stack[len]];
+            if !aabb_overlap(node.min, node.max, min, max) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for &triangle in &self.triangles[node.start..node.start + node.count] {
+                    let tri = triangle_verts(faces, verts, triangle);
+                    if triangle_overlaps_box(tri, center, half_extent) {
+                        overlapping.push(triangle);
+                    }
+                }
+            } else {
+                stack[len] = node.start;
+                stack[len + 1] = node.start + 1;
+                len += 2;
+            }
+        }
+    }
+
+    /// The triangle nearest to `point` within `max_distance`, with the nearest
+    /// point on it and the distance to that point.
+    pub fn nearest_point(
+        &self,
+        faces: &[usize],
+        verts: &[Vec3],
+        point: Vec3,
+        max_distance: f32,
+    ) -> Option<(usize, Vec3, f32)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut nearest = None;
+        let mut best = max_distance * max_distance;
+        let mut stack = [0; MAX_DEPTH];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = self.nodes[stack[len]];
+            if aabb_distance_squared(node.min, node.max, point) > best {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for &triangle in &self.triangles[node.start..node.start + node.count] {
+                    let [v1, v2, v3] = triangle_verts(faces, verts, triangle);
+                    let closest = closest_point_on_triangle(point, v1, v2, v3);
+                    let distance = (closest - point).dot(closest - point);
+                    if distance <= best {
+                        best = distance;
+                        nearest = Some((triangle, closest, distance));
+                    }
+                }
+                continue;
+            }
+
+            // The nearer child is pushed last so that it is visited first, which
+            // tightens `best` sooner.
+            let (mut near, mut far) = (node.start, node.start + 1);
+            let (near_node, far_node) = (self.nodes[near], self.nodes[far]);
+            if aabb_distance_squared(near_node.min, near_node.max, point)
+                > aabb_distance_squared(far_node.min, far_node.max, point)
+            {
+                core::mem::swap(&mut near, &mut far);
+            }
+            stack[len] = far;
+            stack[len + 1] = near;
+            len += 2;
+        }
+
+        nearest.map(|(triangle, closest, distance)| (triangle, closest, libm::sqrtf(distance)))
+    }
+
+    // Visits the leaves hit by `ray` closer than `max_t`, nearest child first.
+    // `visit` may shrink `max_t`, and stops the traversal by returning true.
+    fn traverse_ray(
+        &self,
+        ray: &Ray,
+        max_t: &mut f32,
+        mut visit: impl FnMut(usize, &mut f32) -> bool,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = [0; MAX_DEPTH];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = self.nodes[stack[len]];
+            if ray_aabb(ray, node.min, node.max).is_none_or(|(near, _)| near > *max_t) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for &triangle in &self.triangles[node.start..node.start + node.count] {
+                    if visit(triangle, max_t) {
+                        return;
+                    }
+                }
+                continue;
+            }
+
+            let entry = |child: usize| {
+                let child = self.nodes[child];
+                ray_aabb(ray, child.min, child.max).map_or(f32::INFINITY, |(near, _)| near)
+            };
+            let (mut near, mut far) = (node.start, node.start + 1);
+            if entry(near) > entry(far) {
+                core::mem::swap(&mut near, &mut far);
+            }
+            stack[len] = far;
+            stack[len + 1] = near;
+            len += 2;
+        }
+    }
+
+    fn subdivide(
+        &mut self,
+        index: usize,
+        faces: &[usize],
+        verts: &[Vec3],
+        triangle_bounds: &[(Vec3, Vec3, Vec3)],
+        depth: usize,
+    ) {
+        let BvhNode { start, count, .. } = self.nodes[index];
+        let (min, max) = self.leaf_bounds(start, count, faces, verts);
+        self.nodes[index].min = min;
+        self.nodes[index].max = max;
+
+        // Leaves at the depth limit can hold more than `MAX_LEAF_SIZE`, which keeps
+        // the traversal stacks from overflowing.
+        if count <= MAX_LEAF_SIZE || depth + 1 >= MAX_DEPTH / 2 {
+            return;
+        }
+        let Some((axis, split)) =
+            self.find_split(start, count, triangle_bounds, surface_area(min, max))
+        else {
+            return;
+        };
+
+        // Partition the triangles around the split plane.
+        let mut left = start;
+        let mut right = start + count;
+        while left < right {
+            if component(triangle_bounds[self.triangles[left]].0, axis) < split {
+                left += 1;
+            } else {
+                right -= 1;
+                self.triangles.swap(left, right);
+            }
+        }
+        let left_count = left - start;
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let children = self.nodes.len();
+        self.nodes.push(BvhNode {
+            min: Vec3::ZERO,
+            max: Vec3::ZERO,
+            start,
+            count: left_count,
+        });
+        self.nodes.push(BvhNode {
+            min: Vec3::ZERO,
+            max: Vec3::ZERO,
+            start: left,
+            count: count - left_count,
+        });
+        self.nodes[index].start = children;
+        self.nodes[index].count = 0;
+
+        self.subdivide(children, faces, verts, triangle_bounds, depth + 1);
+        self.subdivide(children + 1, faces, verts, triangle_bounds, depth + 1);
+    }
+
+    // The axis and position of the cheapest split by the surface area heuristic,
+    // or none if keeping the node as a leaf is cheaper.
+    //
+    // https://jacco.ompf2.com/2022/04/21/how-to-build-a-bvh-part-3-quick-builds/
+    fn find_split(
+        &self,
+        start: usize,
+        count: usize,
+        triangle_bounds: &[(Vec3, Vec3, Vec3)],
+        parent_area: f32,
+    ) -> Option<(usize, f32)> {
+        let empty = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+        let triangles = &self.triangles[start..start + count];
+        let (centroid_min, centroid_max) = triangles.iter().fold(empty, |(min, max), &triangle| {
+            let centroid = triangle_bounds[triangle].0;
+            (min_vec3(min, centroid), max_vec3(max, centroid))
+        });
+
+        // Triangles are binned by centroid, and each bin grows to the bounds of
+        // its triangles.
+        let mut best: Option<(usize, f32, f32)> = None;
+        for axis in 0..3 {
+            let low = component(centroid_min, axis);
+            let high = component(centroid_max, axis);
+            if high - low <= f32::EPSILON {
+                continue;
+            }
+
+            let mut bins = [(0, empty.0, empty.1); BINS];
+            let scale = BINS as f32 / (high - low);
+            for &triangle in triangles {
+                let (centroid, min, max) = triangle_bounds[triangle];
+                let bin = (((component(centroid, axis) - low) * scale) as usize).min(BINS - 1);
+                bins[bin].0 += 1;
+                bins[bin].1 = min_vec3(bins[bin].1, min);
+                bins[bin].2 = max_vec3(bins[bin].2, max);
+            }
+
+            // Sweep from both sides to get the count and area left and right of
+            // every plane between bins.
+            let mut left = [(0usize, 0.0f32); BINS - 1];
+            let mut right = [(0usize, 0.0f32); BINS - 1];
+            let (mut left_count, mut left_min, mut left_max) = (0, empty.0, empty.1);
+            let (mut right_count, mut right_min, mut right_max) = (0, empty.0, empty.1);
+            for i in 0..BINS - 1 {
+                let bin = bins[i];
+                left_count += bin.0;
+                left_min = min_vec3(left_min, bin.1);
+                left_max = max_vec3(left_max, bin.2);
+                left[i] = (left_count, surface_area(left_min, left_max));
+
+                let bin = bins[BINS - 1 - i];
+                right_count += bin.0;
+                right_min = min_vec3(right_min, bin.1);
+                right_max = max_vec3(right_max, bin.2);
+                right[BINS - 2 - i] = (right_count, surface_area(right_min, right_max));
+            }
+
+            for i in 0..BINS - 1 {
+                let (left_count, left_area) = left[i];
+                let (right_count, right_area) = right[i];
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+                let cost = left_count as f32 * left_area + right_count as f32 * right_area;
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    let split = low + (i + 1) as f32 / scale;
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+
+        let (axis, split, cost) = best?;
+        let leaf_cost = count as f32 * parent_area;
+        (TRAVERSAL_COST * parent_area + cost < leaf_cost).then_some((axis, split))
+    }
+
+    fn leaf_bounds(
+        &self,
+        start: usize,
+        count: usize,
+        faces: &[usize],
+        verts: &[Vec3],
+    ) -> (Vec3, Vec3) {
+        self.triangles[start..start + count].iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &triangle| {
+                triangle_verts(faces, verts, triangle)
+                    .into_iter()
+                    .fold((min, max), |(min, max), v| {
+                        (min_vec3(min, v), max_vec3(max, v))
+                    })
+            },
+        )
+    }
+}
+
+fn triangle_verts(faces: &[usize], verts: &[Vec3], triangle: usize) -> [Vec3; 3] {
+    [
+        verts[faces[triangle * 3]],
+        verts[faces[triangle * 3 + 1]],
+        verts[faces[triangle * 3 + 2]],
+    ]
+}
+
+fn component(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+fn min_vec3(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn max_vec3(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+fn surface_area(min: Vec3, max: Vec3) -> f32 {
+    let extent = max - min;
+    if extent.x < 0.0 {
+        // Empty bounds.
+        return 0.0;
+    }
+    2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+}
+
+fn aabb_overlap(min1: Vec3, max1: Vec3, min2: Vec3, max2: Vec3) -> bool {
+    min1.x <= max2.x
+        && max1.x >= min2.x
+        && min1.y <= max2.y
+        && max1.y >= min2.y
+        && min1.z <= max2.z
+        && max1.z >= min2.z
+}
+
+fn aabb_distance_squared(min: Vec3, max: Vec3, point: Vec3) -> f32 {
+    let outside = |p: f32, min: f32, max: f32| (min - p).max(0.0).max(p - max);
+    let d = Vec3::new(
+        outside(point.x, min.x, max.x),
+        outside(point.y, min.y, max.y),
+        outside(point.z, min.z, max.z),
+    );
+    d.dot(d)
+}
+
+// Separating axis test between a triangle and a box, over the box axes, the
+// triangle normal, and the cross products of their edges.
+//
+// https://fileadmin.cs.lth.se/cs/Personal/Tomas_Akenine-Moller/code/tribox_tam.pdf
+fn triangle_overlaps_box(tri: [Vec3; 3], center: Vec3, half_extent: Vec3) -> bool {
+    let v = tri.map(|v| v - center);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    let box_axes = [Vec3::x(1.0), Vec3::y(1.0), Vec3::z(1.0)];
+
+    let separated = |axis: Vec3| {
+        if axis.dot(axis) < 1e-12 {
+            return false;
+        }
+        let p = v.map(|v| v.dot(axis));
+        let r = half_extent.x * libm::fabsf(axis.x)
+            + half_extent.y * libm::fabsf(axis.y)
+            + half_extent.z * libm::fabsf(axis.z);
+        p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+    };
+
+    if box_axes.into_iter().any(&separated) || separated(edges[0].cross(edges[1])) {
+        return false;
+    }
+    !box_axes
+        .into_iter()
+        .any(|axis| edges.into_iter().any(|edge| separated(axis.cross(edge))))
+}
+
+// From Real-Time Collision Detection, 5.1.5.
+fn closest_point_on_triangle(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    // Small random triangles scattered through a 10 unit cube.
+    fn soup(rng: &mut Rng, count: usize) -> (Vec<usize>, Vec<Vec3>) {
+        let mut point =
+            |scale: f32| Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * scale;
+        let mut verts = Vec::with_capacity(count * 3);
+        for _ in 0..count {
+            let center = point(10.0);
+            for _ in 0..3 {
+                verts.push(center + point(2.0) - Vec3::splat(1.0));
+            }
+        }
+        ((0..count * 3).collect(), verts)
+    }
+
+    fn random_ray(rng: &mut Rng) -> Ray {
+        let mut point = || Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
+        let origin = point() * 14.0 - Vec3::splat(2.0);
+        let target = point() * 10.0;
+        Ray::new(origin, target - origin)
+    }
+
+    fn brute_closest_hit(faces: &[usize], verts: &[Vec3], ray: &Ray) -> Option<(usize, f32)> {
+        (0..faces.len() / 3)
+            .filter_map(|triangle| {
+                let [v1, v2, v3] = triangle_verts(faces, verts, triangle);
+                ray_triangle(ray, v1, v2, v3).map(|(t, _)| (triangle, t))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    // Every query matches a scan over all of the triangles.
+    fn check_queries(bvh: &Bvh, faces: &[usize], verts: &[Vec3], rng: &mut Rng) {
+        for _ in 0..200 {
+            let ray = random_ray(rng);
+            let expected = brute_closest_hit(faces, verts, &ray);
+            let actual = bvh.closest_hit(faces, verts, &ray, f32::INFINITY);
+            assert_eq!(
+                actual.map(|(_, t, _)| t),
+                expected.map(|(_, t)| t),
+                "{ray:?}"
+            );
+            assert_eq!(
+                bvh.any_hit(faces, verts, &ray, f32::INFINITY),
+                expected.is_some()
+            );
+
+            // Nothing is hit closer than the nearest hit.
+            if let Some((_, t)) = expected {
+                assert!(bvh.closest_hit(faces, verts, &ray, t).is_none());
+                assert!(!bvh.any_hit(faces, verts, &ray, t));
+            }
+        }
+
+        for _ in 0..50 {
+            let min = Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 10.0;
+            let max = min + Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 3.0;
+            let mut actual = Vec::new();
+            bvh.overlap_box(faces, verts, min, max, &mut actual);
+            actual.sort_unstable();
+            let center = (min + max) / 2.0;
+            let half_extent = (max - min) / 2.0;
+            let expected = (0..faces.len() / 3)
+                .filter(|triangle| {
+                    let tri = triangle_verts(faces, verts, *triangle);
+                    triangle_overlaps_box(tri, center, half_extent)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(actual, expected);
+        }
+
+        for _ in 0..50 {
+            let point =
+                Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 14.0 - Vec3::splat(2.0);
+            let expected = (0..faces.len() / 3)
+                .map(|triangle| {
+                    let [v1, v2, v3] = triangle_verts(faces, verts, triangle);
+                    closest_point_on_triangle(point, v1, v2, v3).distance(point)
+                })
+                .min_by(|a, b| a.total_cmp(b))
+                .unwrap();
+            let (_, closest, distance) = bvh
+                .nearest_point(faces, verts, point, f32::INFINITY)
+                .unwrap();
+            assert!(
+                libm::fabsf(distance - expected) < 1e-4,
+                "{distance} != {expected}"
+            );
+            assert!(libm::fabsf(closest.distance(point) - distance) < 1e-4);
+            assert!(
+                bvh.nearest_point(faces, verts, point, expected * 0.5)
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = Rng::new(1, 0);
+        let (faces, verts) = soup(&mut rng, 300);
+        let bvh = Bvh::new(&faces, &verts);
+        check_queries(&bvh, &faces, &verts, &mut rng);
+    }
+
+    #[test]
+    fn refit_queries_match_brute_force() {
+        let mut rng = Rng::new(2, 0);
+        let (faces, mut verts) = soup(&mut rng, 300);
+        let mut bvh = Bvh::new(&faces, &verts);
+
+        // Sway the triangles like a skinned mesh, without rebuilding.
+        for v in verts.iter_mut() {
+            *v += Vec3::new(libm::sinf(v.y), 0.0, libm::cosf(v.x)) * 2.0;
+        }
+        bvh.refit(&faces, &verts);
+        check_queries(&bvh, &faces, &verts, &mut rng);
+    }
+}
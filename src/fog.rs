@@ -0,0 +1,77 @@
+use crate::{camera::Camera, raster};
+use rast::tint::*;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FogMode {
+    // No fog before `start`, full fog from `end`.
+    Linear { start: f32, end: f32 },
+    Exponential,
+    ExponentialSquared,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: Srgb,
+    // Only used by the exponential modes.
+    pub density: f32,
+}
+
+impl Fog {
+    /// How much of the fog color is mixed in at view space `distance`, from 0 to 1.
+    pub fn factor(&self, distance: f32) -> f32 {
+        let factor = match self.mode {
+            FogMode::Linear { start, end } => (distance - start) / (end - start),
+            FogMode::Exponential => 1.0 - libm::expf(-self.density * distance),
+            FogMode::ExponentialSquared => {
+                let d = self.density * distance;
+                1.0 - libm::expf(-d * d)
+            }
+        };
+        factor.clamp(0.0, 1.0)
+    }
+
+    pub fn apply(&self, color: Srgb, distance: f32) -> Srgb {
+        raster::blend_srgb(color, self.color, self.factor(distance))
+    }
+}
+
+// The draw paths write different values to the zbuffer, both of which are 0 at the
+// near plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthConvention {
+    // `(z - nearz) / farz`, written by `model::draw_model` and the other functions
+    // that use the Euler angle transforms in `math`.
+    Linear,
+    // Normalized device depth from `compute_perspective_proj_matrix`, written by
+    // the matrix draw functions.
+    Perspective,
+}
+
+/// View space distance from the camera plane for a zbuffer value.
+pub fn linearize_depth(depth: f32, convention: DepthConvention, camera: &Camera) -> f32 {
+    match convention {
+        DepthConvention::Linear => depth * camera.farz + camera.nearz,
+        DepthConvention::Perspective => {
+            camera.farz * camera.nearz / (camera.farz - depth * (camera.farz - camera.nearz))
+        }
+    }
+}
+
+/// Fogs every pixel with depth. Pixels behind alpha blended faces are fogged by
+/// the depth of the opaque surface behind them; use `model::DrawPass::fog` to fog
+/// faces as they are drawn instead.
+pub fn apply_fog(
+    fog: &Fog,
+    frame_buffer: &mut [Srgb],
+    zbuffer: &[f32],
+    camera: &Camera,
+    convention: DepthConvention,
+) {
+    debug_assert_eq!(frame_buffer.len(), zbuffer.len());
+    for (pixel, depth) in frame_buffer.iter_mut().zip(zbuffer.iter()) {
+        if *depth < 1.0 {
+            *pixel = fog.apply(*pixel, linearize_depth(*depth, convention, camera));
+        }
+    }
+}
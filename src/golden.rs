@@ -0,0 +1,299 @@
+// Golden image tests. Every scene is rendered headlessly and compared with its
+// reference in `tests/golden`. On failure, the render and a diff image are
+// written to `target/golden`, where pixels that are off by more than
+// `TOLERANCE` are red and the rest are the dimmed reference.
+//
+// After an intended change to the output, check the renders in `target/golden`
+// and re-bless every reference with:
+//
+//     cargo test --lib golden::bless -- --ignored
+//
+// Images are binary PPMs, which most image viewers can open.
+
+use crate::{
+    camera::Camera,
+    io,
+    math::*,
+    model,
+    scene::{self, Scene},
+    shadow::{Light, LightKind, ShadowMap},
+    target::RenderTarget,
+};
+use rast::tint::*;
+use std::path::PathBuf;
+
+// Largest difference allowed in any channel of a pixel.
+const TOLERANCE: u8 = 2;
+// Fraction of pixels that may exceed `TOLERANCE`, since float differences between
+// targets can move a triangle edge by a pixel.
+const MAX_BAD_PIXELS: f32 = 0.001;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 180;
+
+const SCENES: [(&str, fn() -> RenderTarget); 4] = [
+    ("teapots_shaded", teapots_shaded),
+    ("teapots_shadowed", teapots_shadowed),
+    ("teapots_wireframe", teapots_wireframe),
+    ("obb_wireframes", obb_wireframes),
+];
+
+#[test]
+fn golden_teapots_shaded() {
+    check("teapots_shaded");
+}
+
+#[test]
+fn golden_teapots_shadowed() {
+    check("teapots_shadowed");
+}
+
+#[test]
+fn golden_teapots_wireframe() {
+    check("teapots_wireframe");
+}
+
+#[test]
+fn golden_obb_wireframes() {
+    check("obb_wireframes");
+}
+
+#[test]
+#[ignore = "overwrites the references, run explicitly to re-bless"]
+fn bless() {
+    std::fs::create_dir_all(reference_dir()).expect("could not create `tests/golden`");
+    for (name, render) in SCENES {
+        let target = render();
+        let path = reference_dir().join(format!("{name}.ppm"));
+        write_ppm(&path, &target.color, target.width, target.height);
+        println!("blessed {}", path.display());
+    }
+}
+
+fn check(name: &str) {
+    let (_, render) = SCENES
+        .iter()
+        .find(|(scene, _)| *scene == name)
+        .expect("unknown scene");
+    let target = render();
+
+    let reference_path = reference_dir().join(format!("{name}.ppm"));
+    let Some((width, height, reference)) = read_ppm(&reference_path) else {
+        panic!(
+            "missing reference `{}`, bless it with `cargo test --lib golden::bless -- --ignored`",
+            reference_path.display()
+        );
+    };
+    assert_eq!(
+        (width, height),
+        (target.width, target.height),
+        "`{name}` was rendered at a different size than its reference"
+    );
+
+    let mut diff = Vec::with_capacity(reference.len());
+    let mut bad_pixels = 0;
+    for (actual, expected) in target.color.iter().zip(reference.iter()) {
+        let off = [
+            actual.r.abs_diff(expected.r),
+            actual.g.abs_diff(expected.g),
+            actual.b.abs_diff(expected.b),
+        ];
+        if off.into_iter().any(|off| off > TOLERANCE) {
+            bad_pixels += 1;
+            diff.push(Srgb::from_rgb(255, 0, 0));
+        } else {
+            diff.push(Srgb::from_rgb(
+                expected.r / 3,
+                expected.g / 3,
+                expected.b / 3,
+            ));
+        }
+    }
+
+    let max_bad_pixels = (width * height) as f32 * MAX_BAD_PIXELS;
+    if bad_pixels as f32 > max_bad_pixels {
+        let out = output_dir();
+        std::fs::create_dir_all(&out).expect("could not create `target/golden`");
+        let actual_path = out.join(format!("{name}.ppm"));
+        let diff_path = out.join(format!("{name}.diff.ppm"));
+        write_ppm(&actual_path, &target.color, width, height);
+        write_ppm(&diff_path, &diff, width, height);
+        panic!(
+            "`{name}` differs from its reference in {bad_pixels} pixels (at most {} allowed), \
+             see `{}` and `{}`",
+            max_bad_pixels as usize,
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+// Scenes. Every scene is drawn from scratch so that tests can run in any order,
+// and only from assets that are checked in.
+
+fn teapots_shaded() -> RenderTarget {
+    render_teapots(None, model::DebugView::Shaded)
+}
+
+fn teapots_shadowed() -> RenderTarget {
+    let light = Light {
+        kind: LightKind::Directional { half_extent: 30.0 },
+        translation: Vec3::new(-30.0, 40.0, -30.0),
+        pitch: 45f32.to_radians(),
+        yaw: 45f32.to_radians(),
+        nearz: 1.0,
+        farz: 150.0,
+    };
+    render_teapots(Some(&light), model::DebugView::Shaded)
+}
+
+fn teapots_wireframe() -> RenderTarget {
+    render_teapots(None, model::DebugView::Wireframe)
+}
+
+// Three teapots in a row under a turned parent, the middle one spun in place.
+fn render_teapots(light: Option<&Light>, debug_view: model::DebugView) -> RenderTarget {
+    let mut scene = Scene::default();
+    let teapot = scene.add_model(load_teapot());
+    let pivot = scene.add_node(None);
+    scene.node_mut(pivot).rotation = Quat::from_pitch_yaw_roll(Vec3::new(0.0, 0.3, 0.0));
+    for i in 0..3 {
+        let node = scene.add_node(Some(pivot));
+        let node_mut = scene.node_mut(node);
+        node_mut.translation = Vec3::x((i as f32 - 1.0) * 12.0);
+        if i == 1 {
+            node_mut.rotation = Quat::from_pitch_yaw_roll(Vec3::new(0.0, 1.2, 0.0));
+        }
+        node_mut.model = Some(teapot);
+    }
+    scene.update();
+
+    let shadow_map = light.map(|light| {
+        let mut shadow_map = ShadowMap::new(512, 512);
+        shadow_map.begin(light);
+        scene::render_shadow_map(&scene, &mut shadow_map);
+        shadow_map
+    });
+
+    let mut target = clear();
+    scene::render(
+        &mut scene,
+        &mut target.color,
+        &mut target.depth,
+        &teapot_camera(),
+        WIDTH,
+        HEIGHT,
+        shadow_map.as_ref(),
+        None,
+        debug_view,
+    );
+    target
+}
+
+fn obb_wireframes() -> RenderTarget {
+    let teapot = load_teapot();
+    let obb = model::compute_obb(&teapot);
+    let camera = teapot_camera();
+
+    let mut target = clear();
+    for (i, color) in [
+        Srgb::from_rgb(255, 0, 0),
+        Srgb::from_rgb(0, 255, 0),
+        Srgb::from_rgb(0, 0, 255),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let angle = i as f32 * 0.6;
+        let model_matrix = compute_model_matrix(
+            Vec3::x((i as f32 - 1.0) * 12.0),
+            Quat::from_pitch_yaw_roll(Vec3::new(angle, angle, 0.0)),
+            Vec3::splat(1.0),
+        );
+        model::debug_draw_obb_matrix(
+            &mut target.color,
+            &mut target.depth,
+            WIDTH,
+            HEIGHT,
+            &camera,
+            obb,
+            model_matrix,
+            color,
+        );
+    }
+    target
+}
+
+fn teapot_camera() -> Camera {
+    Camera {
+        translation: Vec3::new(0.0, 10.0, -40.0),
+        pitch: 15f32.to_radians(),
+        yaw: 0.0,
+        fov: 60f32.to_radians(),
+        nearz: 0.1,
+        farz: 100.0,
+    }
+}
+
+fn load_teapot() -> model::Model {
+    io::debug_obj_file("assets/teapot.obj", Vec::new()).expect("could not load `teapot.obj`")
+}
+
+fn clear() -> RenderTarget {
+    let mut target = RenderTarget::new(WIDTH, HEIGHT);
+    target.color.fill(Srgb::from_rgb(82, 82, 82));
+    target
+}
+
+fn reference_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+// Binary PPM, see https://netpbm.sourceforge.net/doc/ppm.html. Alpha is dropped.
+
+fn write_ppm(path: &std::path::Path, pixels: &[Srgb], width: usize, height: usize) {
+    let mut bytes = format!("P6\n{width} {height}\n255\n").into_bytes();
+    for pixel in pixels {
+        bytes.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    std::fs::write(path, bytes).unwrap_or_else(|_| panic!("could not write `{}`", path.display()));
+}
+
+fn read_ppm(path: &std::path::Path) -> Option<(usize, usize, Vec<Srgb>)> {
+    let bytes = std::fs::read(path).ok()?;
+
+    // The header is four whitespace separated fields, followed by a single
+    // whitespace character before the pixels.
+    let mut fields = Vec::with_capacity(4);
+    let mut cursor = 0;
+    while fields.len() < 4 {
+        while bytes.get(cursor)?.is_ascii_whitespace() {
+            cursor += 1;
+        }
+        let start = cursor;
+        while !bytes.get(cursor)?.is_ascii_whitespace() {
+            cursor += 1;
+        }
+        fields.push(core::str::from_utf8(&bytes[start..cursor]).ok()?);
+    }
+    cursor += 1;
+
+    if fields[0] != "P6" || fields[3] != "255" {
+        return None;
+    }
+    let width: usize = fields[1].parse().ok()?;
+    let height: usize = fields[2].parse().ok()?;
+    let pixels = bytes.get(cursor..cursor + width * height * 3)?;
+    Some((
+        width,
+        height,
+        pixels
+            .chunks(3)
+            .map(|rgb| Srgb::from_rgb(rgb[0], rgb[1], rgb[2]))
+            .collect(),
+    ))
+}
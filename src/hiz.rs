@@ -0,0 +1,135 @@
+use crate::{camera::Camera, math::*, model};
+
+// Hierarchical depth buffer for occlusion culling. Each level halves the one
+// below it and keeps the farthest depth of the texels it covers, so a box whose
+// nearest point is behind a texel of any level is hidden behind everything that
+// was drawn into the zbuffer there.
+//
+// Only works with the normalized device depth written by the matrix draw
+// functions.
+#[derive(Default)]
+pub struct DepthPyramid {
+    // The first level is half the size of the zbuffer.
+    pub levels: Vec<(usize, usize, Vec<f32>)>,
+    // Number of boxes `occluded` has culled since the last `build`.
+    pub culled: usize,
+}
+
+impl DepthPyramid {
+    /// Rebuilds every level from `zbuffer`, reusing the allocations of the last
+    /// build.
+    pub fn build(&mut self, zbuffer: &[f32], width: usize, height: usize) {
+        debug_assert_eq!(zbuffer.len(), width * height);
+        self.culled = 0;
+
+        let mut level = 0;
+        let (mut src_width, mut src_height) = (width, height);
+        while src_width > 1 || src_height > 1 {
+            let (dst_width, dst_height) = (src_width.div_ceil(2), src_height.div_ceil(2));
+            if self.levels.len() == level {
+                self.levels.push((0, 0, Vec::new()));
+            }
+
+            let (before, after) = self.levels.split_at_mut(level);
+            let src = match before.last() {
+                Some((_, _, depth)) => depth.as_slice(),
+                None => zbuffer,
+            };
+            let dst = &mut after[0];
+            dst.0 = dst_width;
+            dst.1 = dst_height;
+            dst.2.resize(dst_width * dst_height, 1.0);
+
+            for y in 0..dst_height {
+                for x in 0..dst_width {
+                    // Odd sizes fold their last row and column into the texels
+                    // before them.
+                    let (x0, y0) = (x * 2, y * 2);
+                    let x1 = (x0 + 1).min(src_width - 1);
+                    let y1 = (y0 + 1).min(src_height - 1);
+                    dst.2[y * dst_width + x] = src[y0 * src_width + x0]
+                        .max(src[y0 * src_width + x1])
+                        .max(src[y1 * src_width + x0])
+                        .max(src[y1 * src_width + x1]);
+                }
+            }
+
+            (src_width, src_height) = (dst_width, dst_height);
+            level += 1;
+        }
+        self.levels.truncate(level);
+    }
+
+    /// Whether the box is hidden behind the depth the pyramid was built from, as
+    /// seen with `view_proj_matrix`. Counts culled boxes in `culled`.
+    pub fn occluded(
+        &mut self,
+        view_proj_matrix: &Mat4,
+        width: usize,
+        height: usize,
+        obb: model::Obb,
+        model_matrix: Mat4,
+    ) -> bool {
+        if self.levels.is_empty() {
+            return false;
+        }
+
+        let mut min = Vec2::splat(f32::INFINITY);
+        let mut max = Vec2::splat(f32::NEG_INFINITY);
+        let mut nearest = f32::INFINITY;
+        for corner in model::obb_corners_matrix(obb, model_matrix) {
+            let v = view_proj_matrix.mult_vec4(corner.extend(1.0));
+            // Boxes that cross the near plane can't be projected.
+            if v.w <= 0.0 || v.z < 0.0 {
+                return false;
+            }
+            let x = (v.x / v.w + 1.0) / 2.0 * width as f32;
+            let y = (1.0 - v.y / v.w) / 2.0 * height as f32;
+            min = Vec2::new(min.x.min(x), min.y.min(y));
+            max = Vec2::new(max.x.max(x), max.y.max(y));
+            nearest = nearest.min(v.z / v.w);
+        }
+
+        let min_x = min.x.max(0.0);
+        let min_y = min.y.max(0.0);
+        let max_x = max.x.min(width as f32 - 1.0);
+        let max_y = max.y.min(height as f32 - 1.0);
+        if min_x > max_x || min_y > max_y {
+            // Off screen, which frustum culling handles.
+            return false;
+        }
+
+        // The level where the box covers at most 2x2 texels.
+        let size = (max_x - min_x).max(max_y - min_y).max(1.0);
+        let level = (libm::ceilf(libm::log2f(size)) as usize)
+            .saturating_sub(1)
+            .min(self.levels.len() - 1);
+        let (level_width, level_height, depth) = &self.levels[level];
+        let scale = (1 << (level + 1)) as f32;
+        let texel = |v: f32, size: usize| ((v / scale) as usize).min(size - 1);
+        let (x0, x1) = (texel(min_x, *level_width), texel(max_x, *level_width));
+        let (y0, y1) = (texel(min_y, *level_height), texel(max_y, *level_height));
+
+        let mut farthest = 0.0f32;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                farthest = farthest.max(depth[y * level_width + x]);
+            }
+        }
+
+        let occluded = nearest > farthest;
+        if occluded {
+            self.culled += 1;
+        }
+        occluded
+    }
+}
+
+/// View projection matrix of the matrix draw functions for `camera`.
+pub fn view_proj_matrix(camera: &Camera, width: usize, height: usize) -> Mat4 {
+    compute_perspective_proj_matrix(camera, width, height).mult_mat4(&compute_view_matrix(
+        camera.translation,
+        camera.yaw,
+        camera.pitch,
+    ))
+}
@@ -1,4 +1,5 @@
 use crate::math::*;
+use crate::model::Material;
 use rast::tint::Srgb;
 
 pub fn debug_read_file(path: &str) -> Option<Vec<u8>> {
@@ -30,6 +31,142 @@ pub fn debug_image_file(path: &str) -> Option<(usize, usize, Vec<Srgb>)> {
     Some((width as usize, height as usize, pixels))
 }
 
+pub fn debug_cube_file(path: &str) -> Option<crate::post::Lut3d> {
+    let cube = debug_read_file_to_string(path)?;
+    debug_cube_str(&cube)
+}
+
+/// Parses an Adobe `.cube` 3D LUT. Only `LUT_3D_SIZE` and the table are read, the
+/// domain is assumed to be 0 to 1. Sizes below 2 are rejected.
+pub fn debug_cube_str(cube: &str) -> Option<crate::post::Lut3d> {
+    let mut size = None;
+    let mut table = Vec::new();
+    for line in cube.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+            size = Some(value.trim().parse::<usize>().ok()?);
+            continue;
+        }
+        if line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // TITLE, DOMAIN_MIN, DOMAIN_MAX, ...
+            continue;
+        }
+
+        let mut values = line.split_whitespace().map(|v| v.parse::<f32>().ok());
+        table.push([values.next()??, values.next()??, values.next()??]);
+    }
+
+    let size = size.filter(|size| *size >= 2)?;
+    (table.len() == size * size * size).then_some(crate::post::Lut3d { size, table })
+}
+
+pub fn debug_bmfont_file(path: &str) -> Option<crate::text::Font> {
+    let fnt = debug_read_file_to_string(path)?;
+    let dir = std::path::Path::new(path).parent()?;
+    // Pages are converted with `img_to_srgb.py` next to the descriptor.
+    debug_bmfont_str(&fnt, |file| {
+        debug_image_file(dir.join(file).with_extension("bin").to_str()?)
+    })
+}
+
+/// Parses a BMFont (AngelCode) text descriptor, loading every page by its file
+/// name with `load_page`. The binary and XML formats and fonts packed into
+/// separate channels are not supported.
+pub fn debug_bmfont_str(
+    fnt: &str,
+    mut load_page: impl FnMut(&str) -> Option<(usize, usize, Vec<Srgb>)>,
+) -> Option<crate::text::Font> {
+    use crate::text::{Font, Glyph};
+    use std::collections::HashMap;
+
+    // `tag key=value key="quoted value" ...`
+    fn attributes(line: &str) -> Option<(&str, HashMap<&str, &str>)> {
+        let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+        let mut attributes = HashMap::new();
+        while let Some((key, value)) = rest.trim_start().split_once('=') {
+            let (value, remaining) = match value.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"')?,
+                None => value.split_once(' ').unwrap_or((value, "")),
+            };
+            attributes.insert(key, value);
+            rest = remaining;
+        }
+        Some((tag, attributes))
+    }
+
+    let mut line_height = None;
+    let mut glyphs = HashMap::new();
+    let mut kerning = HashMap::new();
+    let mut pages = Vec::new();
+    for line in fnt.lines() {
+        let (tag, attributes) = attributes(line.trim())?;
+        let get = |key: &str| attributes.get(key)?.parse::<i32>().ok();
+        // Indices and sizes, which must not be negative.
+        let get_usize = |key: &str| attributes.get(key)?.parse::<usize>().ok();
+        let get_char = |key: &str| char::from_u32(attributes.get(key)?.parse::<u32>().ok()?);
+        match tag {
+            "common" => line_height = Some(get("lineHeight")? as f32),
+            "page" => {
+                let id = get_usize("id")?;
+                if pages.len() <= id {
+                    pages.resize_with(id.checked_add(1)?, || None);
+                }
+                pages[id] = Some(load_page(attributes.get("file")?)?);
+            }
+            "char" => {
+                // Some tools write an `id=-1` glyph for missing characters.
+                let Some(c) = get_char("id") else {
+                    continue;
+                };
+                glyphs.insert(
+                    c,
+                    Glyph {
+                        page: get_usize("page")?,
+                        x: get_usize("x")?,
+                        y: get_usize("y")?,
+                        width: get_usize("width")?,
+                        height: get_usize("height")?,
+                        offset: Vec2::new(get("xoffset")? as f32, get("yoffset")? as f32),
+                        advance: get("xadvance")? as f32,
+                    },
+                );
+            }
+            "kerning" => {
+                kerning.insert(
+                    (get_char("first")?, get_char("second")?),
+                    get("amount")? as f32,
+                );
+            }
+            // info, chars, kernings, ...
+            _ => {}
+        }
+    }
+
+    let pages = pages.into_iter().collect::<Option<Vec<_>>>()?;
+    // The end of a glyph can overflow with out of range values.
+    let fits = |start: usize, size: usize, end: usize| {
+        start
+            .checked_add(size)
+            .is_some_and(|glyph_end| glyph_end <= end)
+    };
+    glyphs
+        .values()
+        .all(|glyph: &Glyph| {
+            pages.get(glyph.page).is_some_and(|(width, height, _)| {
+                fits(glyph.x, glyph.width, *width) && fits(glyph.y, glyph.height, *height)
+            })
+        })
+        .then_some(Font {
+            line_height: line_height?,
+            glyphs,
+            kerning,
+            pages,
+        })
+}
+
 // # List of geometric vertices, with (x, y, z, [w]) coordinates, w is optional and defaults to 1.0.
 // v 0.123 0.234 0.345 1.0
 // v ...
@@ -57,17 +194,14 @@ pub fn debug_image_file(path: &str) -> Option<(usize, usize, Vec<Srgb>)> {
 // l 5 8 1 2 4 9
 pub fn debug_obj_file(
     path: &str,
-    materials: Vec<(String, (usize, usize, Vec<Srgb>))>,
+    materials: Vec<(String, Material)>,
 ) -> Option<crate::model::Model> {
     let obj = debug_read_file_to_string(path)?;
     debug_obj_str(&obj, materials)
 }
 
-pub fn debug_obj_str(
-    mut obj: &str,
-    materials: Vec<(String, (usize, usize, Vec<Srgb>))>,
-) -> Option<crate::model::Model> {
-    let (millis, (faces, face_textures, verts, uvs, textures)) = glazer::debug_time_millis(|| {
+pub fn debug_obj_str(mut obj: &str, mtls: Vec<(String, Material)>) -> Option<crate::model::Model> {
+    let (millis, model) = glazer::debug_time_millis(|| {
         fn read<F: core::str::FromStr>(input: &mut &str) -> Option<F> {
             let split_at = input.chars().position(|c| c.is_whitespace());
             match split_at {
@@ -91,12 +225,11 @@ pub fn debug_obj_str(
         }
 
         let mut faces = Vec::new();
-        let mut face_textures = Vec::new();
+        let mut face_uvs = Vec::new();
+        let mut face_materials = Vec::new();
         let mut verts = Vec::new();
         let mut uvs = Vec::new();
-        let mut textures = Vec::new();
-
-        let mut texture_index = 0;
+        let mut materials: Vec<Material> = Vec::new();
 
         let input = &mut obj;
         while !input.is_empty() {
@@ -120,8 +253,7 @@ pub fn debug_obj_str(
                             if i == 0 {
                                 faces.push(read::<usize>(&mut v).unwrap() - 1);
                             } else if i == 1 {
-                                face_textures
-                                    .push((read::<usize>(&mut v).unwrap() - 1, texture_index));
+                                face_uvs.push(read::<usize>(&mut v).unwrap() - 1);
                             }
                         }
                     }
@@ -131,34 +263,44 @@ pub fn debug_obj_str(
                     let v3 = read::<usize>(input).unwrap();
                     faces.extend([v1 - 1, v2 - 1, v3 - 1]);
                 }
+                // Faces before the first `usemtl` use the first material.
+                face_materials.push(materials.len().saturating_sub(1));
             } else if line.starts_with("usemtl") {
-                if !textures.is_empty() {
-                    texture_index += 1;
-                }
-                textures.push(
-                    materials
-                        .iter()
-                        .find_map(|(name, texture)| (name == &line[7..]).then_some(texture))
+                materials.push(
+                    mtls.iter()
+                        .find_map(|(name, material)| (name == &line[7..]).then_some(material))
                         .cloned()
                         .unwrap_or_else(|| panic!("failed to find obj mtl: `{}`", &line[7..])),
                 );
             }
         }
 
-        (faces, face_textures, verts, uvs, textures)
+        // Models without materials have no face materials.
+        if materials.is_empty() {
+            face_materials.clear();
+        }
+
+        crate::model::Model {
+            faces,
+            face_uvs,
+            face_materials,
+            verts,
+            uvs,
+            materials,
+            ..Default::default()
+        }
     });
 
     glazer::log!(
         "loaded {} verts, {} faces in {millis:.2}ms",
-        verts.len(),
-        faces.len()
+        model.verts.len(),
+        model.faces.len()
     );
 
-    Some(crate::model::Model {
-        faces,
-        face_textures,
-        verts,
-        uvs,
-        textures,
-    })
+    // Problems are only logged, the functions in `model::util` can clean them up.
+    let report = crate::model::util::validate(&model);
+    if !report.is_clean() {
+        glazer::log!("obj validation:\n{report}");
+    }
+    Some(model)
 }
@@ -5,74 +5,155 @@ use math::*;
 use rast::tint::*;
 
 use crate::{
+    background::Background,
     blender::BlenderMemory,
     camera::{Camera, CameraController},
     neutron::NeutronMemory,
 };
 
+#[allow(unused)]
+mod aa;
+#[allow(unused)]
+mod animation;
+#[allow(unused)]
+mod background;
 #[allow(unused)]
 mod blender;
 #[allow(unused)]
 mod boids;
-mod camera;
+#[allow(unused)]
+mod bvh;
+pub mod camera;
+#[allow(unused)]
+mod fog;
+#[cfg(test)]
+mod golden;
+#[allow(unused)]
+mod hiz;
 mod io;
+#[allow(unused)]
+mod lod;
 pub mod math;
 pub mod model;
 #[allow(unused)]
 mod neutron;
+#[allow(unused)]
+mod post;
+#[allow(unused)]
+mod raster;
+#[allow(unused)]
+mod resolution;
 mod rng;
+#[allow(unused)]
+mod scene;
+#[allow(unused)]
+mod shadow;
+#[allow(unused)]
+mod sprite;
+pub mod target;
+#[allow(unused)]
+mod text;
+#[allow(unused)]
+mod trace;
 
+// Size of the window the platform opens. Frames are drawn at the `width` and
+// `height` the platform passes to each update, which the buffers follow.
 pub const MAX_WIDTH: usize = 640 * 2;
 pub const MAX_HEIGHT: usize = 360 * 2;
 pub const MAX_PIXELS: usize = MAX_WIDTH * MAX_HEIGHT;
 
 const VOLUME: f32 = 0.0;
 
-// Initialization code for the platform. The game code provides a static depth
-// buffer and the game memory on startup. This memory is persisted when hot
+// Initialization code for the platform. The game memory owns all of its buffers,
+// so this can be called any number of times. This memory is persisted when hot
 // reloaded.
 
-pub fn memory() -> Memory<'static> {
-    static mut DEPTH_BUFFER: [f32; MAX_PIXELS] = [1.0; MAX_PIXELS];
-    static mut INIT: bool = false;
-
-    // ## Safety
-    //
-    // `DEPTH_BUFFER` is locally scoped. `INIT` verifies that this function
-    // has only been called once. There cannot exist any other mutable references
-    // to `DEPTH_BUFFER` with safe Rust code.
-    unsafe {
-        if INIT {
-            panic!("tried to call `memory` twice");
-        }
-        INIT = true;
-
-        Memory {
-            #[allow(static_mut_refs)]
-            zbuffer: &mut DEPTH_BUFFER,
-            camera: Camera {
-                translation: Vec3::new(0.0, 100.0, -100.0),
-                pitch: 45f32.to_radians(),
-                yaw: 0.0,
-                fov: 90f32.to_radians(),
-                nearz: 0.1,
-                farz: 1000.0,
-            },
-            divine_comedy: io::debug_audio_file("assets/divine-comedy.bin")
-                .expect("could not load `divine-comedy.bin`"),
+pub fn memory() -> Memory {
+    Memory {
+        target: target::RenderTarget::new(MAX_WIDTH, MAX_HEIGHT),
+        camera: Camera {
+            translation: Vec3::new(0.0, 100.0, -100.0),
+            pitch: 45f32.to_radians(),
+            yaw: 0.0,
+            fov: 90f32.to_radians(),
+            nearz: 0.1,
+            farz: 1000.0,
+        },
+        post: default_post_chain(),
+        // Spreads each traced sample over several frames, so the window stays
+        // responsive while the image converges.
+        tracer: trace::Tracer {
+            rays_per_frame: Some(1 << 16),
             ..Default::default()
-        }
+        },
+        hud: true,
+        // The soundtrack isn't needed to draw, e.g. headless, so without it `audio`
+        // plays silence.
+        divine_comedy: io::debug_audio_file("assets/divine-comedy.bin").unwrap_or_else(|| {
+            glazer::log!("could not load `divine-comedy.bin`");
+            Vec::new()
+        }),
+        ..Default::default()
     }
 }
 
+fn default_post_chain() -> post::PostChain {
+    // Every stage starts off, the number keys turn them on.
+    let mut chain = post::PostChain::default();
+    chain.push(post::Effect::Fxaa, false);
+    chain.push(post::Effect::Sharpen { amount: 0.3 }, false);
+    // Slightly warmer, with lifted shadows.
+    chain.push(
+        post::Effect::ColorGrade(post::Lut3d::from_fn(17, |[r, g, b]| {
+            let lift = |c: f32| 0.04 + c * 0.96;
+            [lift(r * 1.04), lift(g), lift(b * 0.94)]
+        })),
+        false,
+    );
+    chain.push(
+        post::Effect::Vignette {
+            strength: 0.4,
+            radius: 0.6,
+        },
+        false,
+    );
+    chain.push(
+        post::Effect::Dither {
+            pattern: post::DitherPattern::BlueNoise {
+                size: 32,
+                ranks: post::blue_noise(32),
+            },
+            levels: 32,
+        },
+        false,
+    );
+    chain
+}
+
 // Game state.
 
 #[derive(Default)]
-pub struct Memory<'a> {
-    zbuffer: &'a mut [f32],
+pub struct Memory {
+    // Depth for every frame. The color is only drawn to by `render_headless`, the
+    // window's frames are drawn straight into the platform frame buffer.
+    target: target::RenderTarget,
 
     camera: Camera,
     controller: CameraController,
+    debug_view: model::DebugView,
+    supersampler: aa::Supersampler,
+    render_scale: resolution::RenderScale,
+    // Last cursor position in pixels, and whether to pick under it next frame.
+    cursor: Vec2,
+    pick_requested: bool,
+    // Shows the path traced scene instead of the rasterized one.
+    traced: bool,
+    tracer: trace::Tracer,
+    // Frame statistics drawn over the frame.
+    hud: bool,
+    font: text::Font,
+    // Smoothed `delta` for the HUD.
+    frame_time: f32,
 
     #[allow(unused)]
     boid_memory: BoidMemory,
@@ -81,16 +162,168 @@ pub struct Memory<'a> {
     #[allow(unused)]
     blender_memory: BlenderMemory,
 
-    bg: f32,
+    background: Background,
+    fog: Option<fog::Fog>,
+    post: post::PostChain,
     divine_comedy: Vec<i16>,
     play_cursor: usize,
 }
 
 #[unsafe(no_mangle)]
 pub fn handle_input(glazer::PlatformInput { memory, input, .. }: glazer::PlatformInput<Memory>) {
+    handle_debug_view_input(&input, &mut memory.debug_view);
+    handle_post_input(&input, &mut memory.post);
+    handle_supersampling_input(&input, &mut memory.supersampler);
+    handle_render_scale_input(&input, &mut memory.render_scale);
+    handle_pick_input(&input, memory);
+    handle_trace_input(&input, &mut memory.traced);
+    handle_hud_input(&input, &mut memory.hud);
     camera::handle_input(input, &mut memory.camera, &mut memory.controller);
 }
 
+// The key of a fresh key press, releases and repeats are `None`.
+fn pressed_key(input: &glazer::Input) -> Option<glazer::winit::keyboard::KeyCode> {
+    use glazer::winit::{
+        event::{ElementState, KeyEvent, WindowEvent},
+        keyboard::PhysicalKey,
+    };
+
+    match input {
+        glazer::Input::Window(WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(code),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+            ..
+        }) => Some(*code),
+        _ => None,
+    }
+}
+
+// F1 through F7 select a `model::DebugView`.
+fn handle_debug_view_input(input: &glazer::Input, debug_view: &mut model::DebugView) {
+    use glazer::winit::keyboard::KeyCode;
+
+    let index = match pressed_key(input) {
+        Some(KeyCode::F1) => 0,
+        Some(KeyCode::F2) => 1,
+        Some(KeyCode::F3) => 2,
+        Some(KeyCode::F4) => 3,
+        Some(KeyCode::F5) => 4,
+        Some(KeyCode::F6) => 5,
+        Some(KeyCode::F7) => 6,
+        _ => return,
+    };
+    *debug_view = model::DebugView::ALL[index];
+    glazer::log!("debug view: {debug_view:?}");
+}
+
+// The number keys toggle the post processing stages in order.
+fn handle_post_input(input: &glazer::Input, post: &mut post::PostChain) {
+    use glazer::winit::keyboard::KeyCode;
+
+    let index = match pressed_key(input) {
+        Some(KeyCode::Digit1) => 0,
+        Some(KeyCode::Digit2) => 1,
+        Some(KeyCode::Digit3) => 2,
+        Some(KeyCode::Digit4) => 3,
+        Some(KeyCode::Digit5) => 4,
+        Some(KeyCode::Digit6) => 5,
+        Some(KeyCode::Digit7) => 6,
+        Some(KeyCode::Digit8) => 7,
+        Some(KeyCode::Digit9) => 8,
+        _ => return,
+    };
+    if let Some(stage) = post.stages.get_mut(index) {
+        stage.enabled = !stage.enabled;
+        glazer::log!(
+            "{}: {}",
+            stage.effect.name(),
+            if stage.enabled { "on" } else { "off" }
+        );
+    }
+}
+
+// F8 cycles through 1, 4, 9 and 16 samples per pixel.
+fn handle_supersampling_input(input: &glazer::Input, supersampler: &mut aa::Supersampler) {
+    if pressed_key(input) == Some(glazer::winit::keyboard::KeyCode::F8) {
+        supersampler.scale = supersampler.scale % 4 + 1;
+        glazer::log!("supersampling: {}x", supersampler.samples());
+    }
+}
+
+// F9 cycles the render scale through 1, 0.75, 0.5 and automatic, F10 toggles
+// bilinear upscaling.
+fn handle_render_scale_input(input: &glazer::Input, render_scale: &mut resolution::RenderScale) {
+    use glazer::winit::keyboard::KeyCode;
+
+    match pressed_key(input) {
+        Some(KeyCode::F9) => {
+            if render_scale.auto.is_some() {
+                render_scale.auto = None;
+                render_scale.scale = 1.0;
+            } else if render_scale.scale >= 1.0 {
+                render_scale.scale = 0.75;
+            } else if render_scale.scale > 0.5 {
+                render_scale.scale = 0.5;
+            } else {
+                // Hold 60 frames per second.
+                render_scale.auto = Some(resolution::AutoScale::new(1.0 / 60.0, 0.5, 1.0));
+            }
+            if render_scale.auto.is_some() {
+                glazer::log!("render scale: auto");
+            } else {
+                glazer::log!("render scale: {}", render_scale.scale);
+            }
+        }
+        Some(KeyCode::F10) => {
+            render_scale.filter = match render_scale.filter {
+                resolution::UpscaleFilter::Nearest => resolution::UpscaleFilter::Bilinear,
+                resolution::UpscaleFilter::Bilinear => resolution::UpscaleFilter::Nearest,
+            };
+            glazer::log!("upscale filter: {:?}", render_scale.filter);
+        }
+        _ => {}
+    }
+}
+
+// Left clicking logs what is under the cursor.
+fn handle_pick_input(input: &glazer::Input, memory: &mut Memory) {
+    use glazer::winit::event::{ElementState, MouseButton, WindowEvent};
+
+    match input {
+        glazer::Input::Window(WindowEvent::CursorMoved { position, .. }) => {
+            memory.cursor = Vec2::new(position.x as f32, position.y as f32);
+        }
+        glazer::Input::Window(WindowEvent::MouseInput {
+            state: ElementState::Pressed,
+            button: MouseButton::Left,
+            ..
+        }) => {
+            memory.pick_requested = true;
+        }
+        _ => {}
+    }
+}
+
+// F11 flips between the rasterized and the path traced scene.
+fn handle_trace_input(input: &glazer::Input, traced: &mut bool) {
+    if pressed_key(input) == Some(glazer::winit::keyboard::KeyCode::F11) {
+        *traced = !*traced;
+        glazer::log!("path tracing: {}", if *traced { "on" } else { "off" });
+    }
+}
+
+// F12 shows and hides the HUD.
+fn handle_hud_input(input: &glazer::Input, hud: &mut bool) {
+    if pressed_key(input) == Some(glazer::winit::keyboard::KeyCode::F12) {
+        *hud = !*hud;
+    }
+}
+
 #[unsafe(no_mangle)]
 pub fn update_and_render(
     glazer::PlatformUpdate {
@@ -109,84 +342,309 @@ pub fn update_and_render(
         ..
     }: glazer::PlatformUpdate<Memory>,
 ) {
-    window.set_title(&format!("Tea, Sir? - {:.2}", 1.0 / delta));
+    window.set_title(&format!(
+        "Tea, Sir? - {:.2} - {} occluded",
+        1.0 / delta,
+        blender::occluded(&memory.blender_memory)
+    ));
 
     // audio(memory, samples, channels, sample_rate as f32);
+    update(memory, Some(frame_buffer), width, height, delta);
+
+    memory.frame_time = if memory.frame_time > 0.0 {
+        memory.frame_time + (delta - memory.frame_time) * 0.1
+    } else {
+        delta
+    };
+    if memory.hud {
+        draw_hud(memory, frame_buffer, width, height);
+    }
+}
+
+// Frame statistics in the top left corner. Drawn after post processing, at the
+// window resolution, so that it stays legible at any render scale.
+fn draw_hud(memory: &Memory, frame_buffer: &mut [Srgb], width: usize, height: usize) {
+    let mut stats = format!(
+        "{:.0} fps {:.2} ms\n{width}x{height}",
+        1.0 / memory.frame_time,
+        memory.frame_time * 1000.0
+    );
+    if !memory.render_scale.is_native() {
+        stats += &format!(" at {:.2}", memory.render_scale.scale);
+    }
+    if memory.supersampler.samples() > 1 {
+        stats += &format!(" {}x ssaa", memory.supersampler.samples());
+    }
+    if memory.traced {
+        stats += &format!("\n{} samples", memory.tracer.samples());
+    } else {
+        stats += &format!("\n{} occluded", blender::occluded(&memory.blender_memory));
+    }
+    for stage in memory.post.stages.iter().filter(|stage| stage.enabled) {
+        stats += &format!("\n{} {:.2} ms", stage.effect.name(), stage.millis);
+    }
+
+    // Shadowed to read over bright backgrounds.
+    for (offset, color) in [
+        (2.0, Srgb::from_rgb(0, 0, 0)),
+        (0.0, Srgb::from_rgb(255, 255, 255)),
+    ] {
+        text::draw_text(
+            frame_buffer,
+            width,
+            height,
+            &memory.font,
+            &stats,
+            8.0 + offset,
+            8.0 + offset,
+            color,
+            2.0,
+            text::Align::Left,
+        );
+    }
+}
+
+/// Renders `frames` frames of `memory` from `camera` without a window, stepping
+/// the demos by a fixed `delta` every frame, and returns the last frame. This runs
+/// the same code as `update_and_render`, so `memory` can be created with `memory`
+/// and shared with tests and batch tools. Only the blender demo's models and
+/// textures need to be on disk, the soundtrack is optional.
+pub fn render_headless(
+    memory: &mut Memory,
+    camera: Camera,
+    width: usize,
+    height: usize,
+    frames: usize,
+    delta: f32,
+) -> target::RenderTarget {
+    assert!(frames > 0, "must render at least one frame");
+
+    memory.camera = camera;
+    for _ in 0..frames {
+        update(memory, None, width, height, delta);
+    }
+    core::mem::take(&mut memory.target)
+}
+
+// Steps the game by `delta` and draws the frame into `frame_buffer`, or into
+// `memory.target` without one.
+fn update(
+    memory: &mut Memory,
+    frame_buffer: Option<&mut [Srgb]>,
+    width: usize,
+    height: usize,
+    delta: f32,
+) {
     camera::update_camera(&mut memory.camera, &memory.controller, delta);
-    clear(memory, frame_buffer);
+
+    if core::mem::take(&mut memory.pick_requested) {
+        let ray = screen_to_world_ray(
+            width,
+            height,
+            &memory.camera,
+            memory.cursor.x,
+            memory.cursor.y,
+        );
+        match blender::pick(&memory.blender_memory, ray) {
+            Some(hit) => glazer::log!("picked {hit:?}"),
+            None => glazer::log!("picked nothing"),
+        }
+    }
+
+    let mut target = core::mem::take(&mut memory.target);
+    target.resize(width, height);
+    let color: &mut [Srgb] = match frame_buffer {
+        Some(frame_buffer) => frame_buffer,
+        None => &mut target.color,
+    };
+    debug_assert_eq!(color.len(), width * height);
+    render_frame(memory, color, &mut target.depth, width, height, delta);
+    memory.target = target;
+}
+
+fn render_frame(
+    memory: &mut Memory,
+    color: &mut [Srgb],
+    depth: &mut [f32],
+    width: usize,
+    height: usize,
+    delta: f32,
+) {
+    // The traced scene holds still so that samples keep accumulating, and has no
+    // depth for fog.
+    if memory.traced {
+        blender::trace(
+            &memory.blender_memory,
+            &mut memory.tracer,
+            &memory.background,
+            color,
+            width,
+            height,
+            &memory.camera,
+        );
+        depth.fill(1.0);
+        memory.post.run(color, depth, width, height);
+        return;
+    }
+
+    // The scene is drawn into the internal target when the render scale is below
+    // 1, and upscaled into the frame before fog and post processing, which run at
+    // the window resolution.
+    let mut render_scale = core::mem::take(&mut memory.render_scale);
+    render_scale.update(delta);
+    if render_scale.is_native() {
+        render_supersampled(memory, color, depth, width, height, delta);
+    } else {
+        render_scale.resize(width, height);
+        let internal = &mut render_scale.target;
+        render_supersampled(
+            memory,
+            &mut internal.color,
+            &mut internal.depth,
+            internal.width,
+            internal.height,
+            delta,
+        );
+        render_scale.upscale(color, depth, width, height);
+    }
+    memory.render_scale = render_scale;
+
+    // Every pass of the frame adds to the same counts.
+    if memory.debug_view == model::DebugView::Overdraw {
+        model::resolve_overdraw(color);
+    }
+
+    // The blender demo draws with the matrix functions. Fog would hide what the
+    // other debug views show.
+    let shaded = matches!(
+        memory.debug_view,
+        model::DebugView::Shaded | model::DebugView::WireframeOverShaded
+    );
+    if let Some(fog) = memory.fog.as_ref().filter(|_| shaded) {
+        fog::apply_fog(
+            fog,
+            color,
+            depth,
+            &memory.camera,
+            fog::DepthConvention::Perspective,
+        );
+    }
+
+    memory.post.run(color, depth, width, height);
+}
+
+#[allow(unused)]
+fn audio(memory: &mut Memory, samples: &mut [f32], channels: usize, sample_rate: f32) {
+    assert_eq!(sample_rate, 44_100.0);
+    assert_eq!(channels, 2);
+
+    if memory.divine_comedy.is_empty() {
+        samples.fill(0.0);
+        return;
+    }
+    for sample in samples.iter_mut() {
+        if memory.play_cursor >= memory.divine_comedy.len() {
+            memory.play_cursor = 0;
+        }
+        *sample = memory.divine_comedy[memory.play_cursor] as f32 / i16::MAX as f32 * VOLUME;
+        memory.play_cursor += 1;
+    }
+}
+
+// The scene is drawn into the sample buffers when supersampling, and resolved
+// into `frame_buffer`.
+fn render_supersampled(
+    memory: &mut Memory,
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    delta: f32,
+) {
+    let mut supersampler = core::mem::take(&mut memory.supersampler);
+    if supersampler.scale > 1 {
+        supersampler.resize(width, height);
+        let samples = &mut supersampler.target;
+        render_scene(
+            memory,
+            &mut samples.color,
+            &mut samples.depth,
+            samples.width,
+            samples.height,
+            delta,
+        );
+        supersampler.resolve(frame_buffer, zbuffer, width, height);
+    } else {
+        render_scene(memory, frame_buffer, zbuffer, width, height, delta);
+    }
+    memory.supersampler = supersampler;
+}
+
+fn render_scene(
+    memory: &mut Memory,
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    delta: f32,
+) {
+    clear(memory, frame_buffer, zbuffer, width, height);
 
     // NEUTRON
-    // neutron::update(&mut memory._neutron_memory, delta);
+    // neutron::update(&mut memory.neutron_memory, delta);
     // neutron::render(
-    //     &mut memory._neutron_memory,
+    //     &mut memory.neutron_memory,
     //     frame_buffer,
-    //     memory.zbuffer,
+    //     zbuffer,
     //     width,
     //     height,
     //     &memory.camera,
     // );
 
     // BOIDS
-    // boids::update(&mut memory._boid_memory, delta);
+    // boids::update(&mut memory.boid_memory, delta);
     // boids::render(
-    //     &mut memory._boid_memory,
+    //     &mut memory.boid_memory,
     //     frame_buffer,
-    //     &mut memory.zbuffer,
+    //     zbuffer,
     //     width,
     //     height,
     //     &memory.camera,
+    //     memory.debug_view,
     // );
 
     // BLENDER
     blender::render(
         &mut memory.blender_memory,
         frame_buffer,
-        memory.zbuffer,
+        zbuffer,
         &memory.camera,
         width,
         height,
         delta,
+        memory.debug_view,
     );
 }
 
-#[allow(unused)]
-fn audio(memory: &mut Memory, samples: &mut [f32], channels: usize, sample_rate: f32) {
-    assert_eq!(sample_rate, 44_100.0);
-    assert_eq!(channels, 2);
-
-    for sample in samples.iter_mut() {
-        if memory.play_cursor >= memory.divine_comedy.len() {
-            memory.play_cursor = 0;
-        }
-        *sample = memory.divine_comedy[memory.play_cursor] as f32 / i16::MAX as f32 * VOLUME;
-        memory.play_cursor += 1;
-    }
-}
-
-fn clear(memory: &mut Memory, frame_buffer: &mut [Srgb]) {
-    frame_buffer.fill(Srgb::from_rgb(82, 82, 82));
-    memory.zbuffer.fill(1.0);
-}
-
-#[expect(unused)]
-fn fill_background(
-    memory: &mut Memory,
+fn clear(
+    memory: &Memory,
     frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
     width: usize,
     height: usize,
-    delta: f32,
 ) {
-    memory.bg += delta * 50.0;
-    memory.bg %= 255.0;
-    for y in 0..height {
-        for x in 0..width {
-            let index = y * width + x;
-            if memory.zbuffer[index] == f32::MAX {
-                let r = ((x as f32 + memory.bg) % 255.0) as u8;
-                let g = 0;
-                let b = ((y as f32 + memory.bg) % 255.0) as u8;
-                frame_buffer[index] = Srgb::new(r, g, b, 255);
-            }
-        }
+    zbuffer.fill(1.0);
+    // Overdraw counts fragments from zero, see `model::resolve_overdraw`.
+    if memory.debug_view == model::DebugView::Overdraw {
+        frame_buffer.fill(Srgb::from_rgb(0, 0, 0));
+        return;
     }
+    background::draw_background(
+        &memory.background,
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        &memory.camera,
+    );
 }
@@ -0,0 +1,465 @@
+use crate::{
+    camera::Camera,
+    math::*,
+    model::{self, Model},
+};
+use core::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// A level of detail for a model in the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct Lod {
+    // Index into `Scene::models`.
+    pub model: usize,
+    // Smallest projected screen size, as a fraction of the screen height, that
+    // this level is used at.
+    pub screen_size: f32,
+}
+
+/// Picks the level in `lods`, sorted from the most to the least detailed, for an
+/// object with the bounding box `obb` drawn with `model_matrix`. Falls back to the
+/// least detailed level.
+pub fn select_lod(lods: &[Lod], camera: &Camera, obb: model::Obb, model_matrix: Mat4) -> usize {
+    debug_assert!(!lods.is_empty());
+    let size = projected_screen_size(camera, obb, model_matrix);
+    lods.iter()
+        .position(|lod| size >= lod.screen_size)
+        .unwrap_or(lods.len() - 1)
+}
+
+/// Height of the bounding sphere around `obb` as a fraction of the screen height.
+pub fn projected_screen_size(camera: &Camera, obb: model::Obb, model_matrix: Mat4) -> f32 {
+    let center = model_matrix
+        .mult_vec4(((obb.min + obb.max) / 2.0).extend(1.0))
+        .reduce();
+    let m = &model_matrix;
+    let scale = Vec3::new(m.r1.x, m.r2.x, m.r3.x)
+        .length()
+        .max(Vec3::new(m.r1.y, m.r2.y, m.r3.y).length())
+        .max(Vec3::new(m.r1.z, m.r2.z, m.r3.z).length());
+    let radius = (obb.max - obb.min).length() / 2.0 * scale;
+
+    let distance = center.distance(camera.translation);
+    if distance <= radius {
+        return f32::INFINITY;
+    }
+    radius / (distance * libm::tanf(camera.fov / 2.0))
+}
+
+// Quadric error metric simplification (Garland and Heckbert). Edges are
+// collapsed cheapest first until the target face count is reached.
+//
+// Vertices on a UV seam, a material border or an open edge are locked so that
+// the seams and borders keep their shape. Collapses are only done between two
+// unlocked vertices, which each have a single uv.
+
+/// Simplifies `model` down to roughly `ratio` of its faces. Every vertex of the
+/// simplified model keeps the joints, weights and morph target deltas of the
+/// vertex it was collapsed from, along with the skeleton, so it can be skinned and
+/// morphed like the original.
+pub fn simplify(model: &Model, ratio: f32) -> Model {
+    let face_count = model.faces.len() / 3;
+    let target = ((face_count as f32 * ratio.clamp(0.0, 1.0)) as usize).max(1);
+    let has_uvs = !model.face_uvs.is_empty();
+    let has_normals = !model.normals.is_empty();
+
+    let mut verts = model.verts.clone();
+    // The original vertex whose skinning and morph data each vertex uses.
+    let mut sources = (0..verts.len()).collect::<Vec<_>>();
+    let mut uvs = model.uvs.clone();
+    let mut normals = model.normals.clone();
+    let mut faces = model
+        .faces
+        .chunks(3)
+        .map(|face| [face[0], face[1], face[2]])
+        .collect::<Vec<_>>();
+    let mut alive = vec![true; faces.len()];
+
+    let mut adjacent = vec![Vec::new(); verts.len()];
+    for (i, face) in faces.iter().enumerate() {
+        for v in face {
+            adjacent[*v].push(i);
+        }
+    }
+
+    let locked = locked_vertices(model, &faces, &adjacent);
+    // The uv of each unlocked vertex. Locked vertices keep the uvs of their corners.
+    let mut vertex_uvs = vec![None; verts.len()];
+    if has_uvs {
+        for (i, v) in model.faces.iter().enumerate() {
+            vertex_uvs[*v] = Some(model.face_uvs[i]);
+        }
+    }
+
+    let mut quadrics = vec![Quadric::default(); verts.len()];
+    for face in faces.iter() {
+        let q = Quadric::from_plane(verts[face[0]], verts[face[1]], verts[face[2]]);
+        for v in face {
+            quadrics[*v] = quadrics[*v].add(&q);
+        }
+    }
+
+    // Collapses are keyed by their error, and skipped if either vertex changed
+    // since the collapse was pushed.
+    let mut versions = vec![0u32; verts.len()];
+    let mut heap = BinaryHeap::new();
+    for face in faces.iter() {
+        for (a, b) in [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])] {
+            if a < b && !locked[a] && !locked[b] {
+                push_collapse(&mut heap, &verts, &quadrics, &versions, a, b);
+            }
+        }
+    }
+
+    let mut remaining = face_count;
+    while remaining > target {
+        let Some(Reverse((_, a, b, version_a, version_b))) = heap.pop() else {
+            break;
+        };
+        if versions[a] != version_a || versions[b] != version_b {
+            continue;
+        }
+
+        let q = quadrics[a].add(&quadrics[b]);
+        let (position, t) = best_position(&q, verts[a], verts[b]);
+        if collapse_flips(&faces, &alive, &adjacent[a], a, b, position, &verts)
+            || collapse_flips(&faces, &alive, &adjacent[b], b, a, position, &verts)
+        {
+            continue;
+        }
+
+        // Collapse `a` into `b`.
+        verts[b] = position;
+        quadrics[b] = q;
+        if t < 0.5 {
+            sources[b] = sources[a];
+        }
+        if let (Some(uv_a), Some(uv_b)) = (vertex_uvs[a], vertex_uvs[b]) {
+            if t == 0.0 {
+                vertex_uvs[b] = Some(uv_a);
+            } else if t != 1.0 {
+                uvs.push(uvs[uv_a] + (uvs[uv_b] - uvs[uv_a]) * t);
+                vertex_uvs[b] = Some(uvs.len() - 1);
+            }
+        }
+        if has_normals {
+            normals[b] = normals[a].lerp(normals[b], t).normalize_or_zero();
+        }
+
+        let faces_a = core::mem::take(&mut adjacent[a]);
+        for face in faces_a {
+            if !alive[face] {
+                continue;
+            }
+            if faces[face].contains(&b) {
+                alive[face] = false;
+                remaining -= 1;
+                continue;
+            }
+            for v in faces[face].iter_mut() {
+                if *v == a {
+                    *v = b;
+                }
+            }
+            adjacent[b].push(face);
+        }
+        adjacent[b].retain(|face| alive[*face]);
+
+        versions[a] += 1;
+        versions[b] += 1;
+        let mut neighbours = adjacent[b]
+            .iter()
+            .flat_map(|face| faces[*face])
+            .filter(|v| *v != b && !locked[*v])
+            .collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for v in neighbours {
+            push_collapse(&mut heap, &verts, &quadrics, &versions, v.min(b), v.max(b));
+        }
+    }
+
+    // Compact the surviving faces and the vertices they use.
+    let mut remap = vec![usize::MAX; verts.len()];
+    let mut out = Model {
+        materials: model.materials.clone(),
+        morph_targets: model
+            .morph_targets
+            .iter()
+            .map(|target| model::MorphTarget {
+                name: target.name.clone(),
+                ..Default::default()
+            })
+            .collect(),
+        skeleton: model.skeleton.clone(),
+        ..Default::default()
+    };
+    for (i, face) in faces.iter().enumerate() {
+        if !alive[i] {
+            continue;
+        }
+        for (corner, v) in face.iter().enumerate() {
+            if remap[*v] == usize::MAX {
+                remap[*v] = out.verts.len();
+                out.verts.push(verts[*v]);
+                if has_normals {
+                    out.normals.push(normals[*v]);
+                }
+
+                let source = sources[*v];
+                if !model.joints.is_empty() {
+                    out.joints.push(model.joints[source]);
+                    out.weights.push(model.weights[source]);
+                }
+                for (out_target, target) in out.morph_targets.iter_mut().zip(&model.morph_targets) {
+                    out_target
+                        .position_deltas
+                        .push(target.position_deltas[source]);
+                    if !target.normal_deltas.is_empty() {
+                        out_target.normal_deltas.push(target.normal_deltas[source]);
+                    }
+                }
+            }
+            out.faces.push(remap[*v]);
+            if has_uvs {
+                let uv = if locked[*v] {
+                    model.face_uvs[i * 3 + corner]
+                } else {
+                    vertex_uvs[*v].unwrap()
+                };
+                out.face_uvs.push(uv);
+            }
+        }
+        if !model.face_materials.is_empty() {
+            out.face_materials.push(model.face_materials[i]);
+        }
+    }
+    if has_uvs {
+        out.uvs = uvs;
+    }
+
+    out
+}
+
+// Vertices that have more than one uv, touch more than one material or lie on an
+// open or non-manifold edge.
+fn locked_vertices(model: &Model, faces: &[[usize; 3]], adjacent: &[Vec<usize>]) -> Vec<bool> {
+    let mut locked = vec![false; model.verts.len()];
+
+    let mut first_uv = vec![None; model.verts.len()];
+    for (i, v) in model.faces.iter().enumerate() {
+        if model.face_uvs.is_empty() {
+            break;
+        }
+        let uv = model.face_uvs[i];
+        match first_uv[*v] {
+            None => first_uv[*v] = Some(uv),
+            Some(first) if first != uv && model.uvs[first] != model.uvs[uv] => locked[*v] = true,
+            _ => {}
+        }
+    }
+
+    for (v, faces) in adjacent.iter().enumerate() {
+        if model.face_materials.is_empty() {
+            break;
+        }
+        let material = faces.first().map(|face| model.face_materials[*face]);
+        if faces
+            .iter()
+            .any(|face| Some(model.face_materials[*face]) != material)
+        {
+            locked[v] = true;
+        }
+    }
+
+    let mut edges = faces
+        .iter()
+        .flat_map(|face| [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])])
+        .map(|(a, b)| (a.min(b), a.max(b)))
+        .collect::<Vec<_>>();
+    edges.sort_unstable();
+    for run in edges.chunk_by(|a, b| a == b) {
+        if run.len() != 2 {
+            locked[run[0].0] = true;
+            locked[run[0].1] = true;
+        }
+    }
+
+    locked
+}
+
+// The error is stored as its bits, which sort the same as the float for
+// non-negative errors.
+type Collapse = Reverse<(u32, usize, usize, u32, u32)>;
+
+fn push_collapse(
+    heap: &mut BinaryHeap<Collapse>,
+    verts: &[Vec3],
+    quadrics: &[Quadric],
+    versions: &[u32],
+    a: usize,
+    b: usize,
+) {
+    let q = quadrics[a].add(&quadrics[b]);
+    let (position, _) = best_position(&q, verts[a], verts[b]);
+    let error = q.error(position).max(0.0);
+    heap.push(Reverse((error.to_bits(), a, b, versions[a], versions[b])));
+}
+
+// The cheapest of the two endpoints and the midpoint, and how far it lies from
+// `a` to `b`. Solving for the optimal position would move vertices off of their
+// uvs, so only points on the edge are considered.
+fn best_position(q: &Quadric, a: Vec3, b: Vec3) -> (Vec3, f32) {
+    [0.0, 0.5, 1.0]
+        .into_iter()
+        .map(|t| (a.lerp(b, t), t))
+        .min_by(|(p1, _), (p2, _)| q.error(*p1).total_cmp(&q.error(*p2)))
+        .unwrap()
+}
+
+// Whether moving `v` to `position` flips any of its faces that don't also contain
+// `other`, which are removed by the collapse.
+fn collapse_flips(
+    faces: &[[usize; 3]],
+    alive: &[bool],
+    adjacent: &[usize],
+    v: usize,
+    other: usize,
+    position: Vec3,
+    verts: &[Vec3],
+) -> bool {
+    adjacent.iter().any(|face| {
+        if !alive[*face] {
+            return false;
+        }
+        let face = faces[*face];
+        if face.contains(&other) {
+            return false;
+        }
+        let before = verts[face[1]] - verts[face[0]];
+        let before = before.cross(verts[face[2]] - verts[face[0]]);
+        let moved = face.map(|i| if i == v { position } else { verts[i] });
+        let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+        before.dot(after) <= 0.0
+    })
+}
+
+// Symmetric 4x4 matrix, upper triangle stored row by row.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(v1: Vec3, v2: Vec3, v3: Vec3) -> Self {
+        let v1 = v1.to_dvec3();
+        let normal = (v2.to_dvec3() - v1).cross(v3.to_dvec3() - v1);
+        let area = normal.length();
+        if area == 0.0 {
+            return Self::default();
+        }
+        let n = normal / area;
+        let d = -n.dot(v1);
+        let (a, b, c) = (n.x, n.y, n.z);
+        // Weighted by area so that large faces dominate.
+        let q = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        Self(q.map(|x| x * area))
+    }
+
+    fn add(&self, rhs: &Self) -> Self {
+        Self(core::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+
+    fn error(&self, v: Vec3) -> f32 {
+        let [a, b, c, d, e, f, g, h, i, j] = self.0;
+        let (x, y, z) = (v.x as f64, v.y as f64, v.z as f64);
+        let error = a * x * x
+            + 2.0 * b * x * y
+            + 2.0 * c * x * z
+            + 2.0 * d * x
+            + e * y * y
+            + 2.0 * f * y * z
+            + 2.0 * g * y
+            + h * z * z
+            + 2.0 * i * z
+            + j;
+        error as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 8;
+
+    // Flat N x N grid of quads with a uv seam down column 4 and a material border
+    // along row 4.
+    fn grid() -> Model {
+        let index = |x: usize, y: usize| y * (N + 1) + x;
+        let mut model = Model::default();
+        for y in 0..=N {
+            for x in 0..=N {
+                model.verts.push(Vec3::new(x as f32, y as f32, 0.0));
+                model.uvs.push(Vec2::new(x as f32, y as f32) / N as f32);
+            }
+        }
+        // The seam column has a second uv for the faces to its right.
+        let seam_uvs = model.uvs.len();
+        for y in 0..=N {
+            model.uvs.push(Vec2::new(2.0, y as f32 / N as f32));
+        }
+
+        for y in 0..N {
+            for x in 0..N {
+                let quad = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+                for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+                    for corner in [a, b, c] {
+                        let (vx, vy) = quad[corner];
+                        model.faces.push(index(vx, vy));
+                        let uv = if vx == 4 && x >= 4 {
+                            seam_uvs + vy
+                        } else {
+                            index(vx, vy)
+                        };
+                        model.face_uvs.push(uv);
+                    }
+                    model.face_materials.push(if y < 4 { 0 } else { 1 });
+                }
+            }
+        }
+        model.materials = vec![Default::default(); 2];
+        model.joints = vec![[0; 4]; model.verts.len()];
+        model.weights = vec![[1.0, 0.0, 0.0, 0.0]; model.verts.len()];
+        model
+    }
+
+    #[test]
+    fn simplify_keeps_seams_and_borders() {
+        let model = grid();
+        let simplified = simplify(&model, 0.5);
+
+        assert!(simplified.faces.len() < model.faces.len());
+        assert_eq!(simplified.faces.len(), simplified.face_uvs.len());
+        assert_eq!(simplified.faces.len() / 3, simplified.face_materials.len());
+        assert_eq!(simplified.joints.len(), simplified.verts.len());
+        assert_eq!(simplified.weights.len(), simplified.verts.len());
+
+        for v in model.verts.iter() {
+            let (x, y) = (v.x as usize, v.y as usize);
+            let boundary = x == 0 || y == 0 || x == N || y == N;
+            if boundary || x == 4 || y == 4 {
+                assert!(simplified.verts.contains(v), "{v:?} was collapsed");
+            }
+        }
+    }
+}
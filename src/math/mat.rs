@@ -1,4 +1,4 @@
-use crate::math::vec::Vec4;
+use crate::math::vec::{Vec3, Vec4};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -41,6 +41,34 @@ impl Mat4 {
         }
     }
 
+    /// Inverse of a matrix with no projection, i.e. `r4` is `(0, 0, 0, 1)`.
+    #[must_use]
+    pub fn inverse_affine(&self) -> Self {
+        debug_assert_eq!(self.r4, Vec4::w(1.0));
+
+        let c1 = Vec3::new(self.r1.x, self.r2.x, self.r3.x);
+        let c2 = Vec3::new(self.r1.y, self.r2.y, self.r3.y);
+        let c3 = Vec3::new(self.r1.z, self.r2.z, self.r3.z);
+        let translation = Vec3::new(self.r1.w, self.r2.w, self.r3.w);
+
+        // https://en.wikipedia.org/wiki/Invertible_matrix#Inversion_of_3_%C3%97_3_matrices
+        let r1 = c2.cross(c3);
+        let r2 = c3.cross(c1);
+        let r3 = c1.cross(c2);
+        let det = c1.dot(r1);
+        debug_assert!(det != 0.0, "tried to invert a singular matrix");
+        let r1 = r1 / det;
+        let r2 = r2 / det;
+        let r3 = r3 / det;
+
+        Self {
+            r1: r1.extend(-r1.dot(translation)),
+            r2: r2.extend(-r2.dot(translation)),
+            r3: r3.extend(-r3.dot(translation)),
+            r4: Vec4::w(1.0),
+        }
+    }
+
     pub fn mult_mat4(&self, rhs: &Self) -> Self {
         let rhs = rhs.transpose();
 
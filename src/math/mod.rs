@@ -1,9 +1,11 @@
 mod mat;
 mod quat;
+mod ray;
 mod vec;
 
 pub use mat::*;
 pub use quat::*;
+pub use ray::*;
 pub use vec::*;
 
 // These matrix computations were stolen from `glam`.
@@ -31,13 +33,17 @@ pub fn compute_model_matrix(translation: Vec3, rotation: Quat, scale: Vec3) -> M
 #[must_use]
 pub fn compute_view_matrix(translation: Vec3, yaw: f32, pitch: f32) -> Mat4 {
     // https://www.3dgep.com/understanding-the-view-matrix/#The_View_Matrix
+    //
+    // The axes are the same basis used by `vertex_world_to_camera_space`, with
+    // the z axis flipped so that the camera looks down -z, which is what
+    // `compute_perspective_proj_matrix` expects.
 
     let (ysin, ycos) = libm::sincosf(-yaw);
     let (psin, pcos) = libm::sincosf(-pitch);
 
-    let xaxis = Vec3::new(ycos, 0.0, -ysin);
-    let yaxis = Vec3::new(ysin * psin, pcos, ycos * psin);
-    let zaxis = Vec3::new(ysin * pcos, -psin, pcos * ycos);
+    let xaxis = Vec3::new(ycos, 0.0, ysin);
+    let yaxis = Vec3::new(psin * ysin, pcos, -psin * ycos);
+    let zaxis = Vec3::new(-pcos * ysin, psin, pcos * ycos);
 
     Mat4 {
         r1: xaxis.extend(-xaxis.dot(translation)),
@@ -62,6 +68,32 @@ pub fn compute_perspective_proj_matrix(camera: &Camera, width: usize, height: us
     }
 }
 
+#[inline]
+#[must_use]
+pub fn compute_orthographic_proj_matrix(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    nearz: f32,
+    farz: f32,
+) -> Mat4 {
+    let rcp_width = 1.0 / (right - left);
+    let rcp_height = 1.0 / (top - bottom);
+    let r = 1.0 / (nearz - farz);
+    Mat4 {
+        r1: Vec4::new(rcp_width + rcp_width, 0.0, 0.0, -(left + right) * rcp_width),
+        r2: Vec4::new(
+            0.0,
+            rcp_height + rcp_height,
+            0.0,
+            -(top + bottom) * rcp_height,
+        ),
+        r3: Vec4::new(0.0, 0.0, r, r * nearz),
+        r4: Vec4::w(1.0),
+    }
+}
+
 use crate::camera::Camera;
 
 pub fn transform_vertex(translation: Vec3, pitch_yaw_roll: Vec3, v: Vec3) -> Vec3 {
@@ -117,6 +149,60 @@ pub fn triangle_camera_to_screen_space(
     )
 }
 
+/// Clips the segment to the view frustum and projects what is left, so that
+/// segments partly behind the camera or off screen keep their visible part.
+pub fn line_world_to_screen_space_clipped(
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    v1: Vec3,
+    v2: Vec3,
+) -> Option<(Vec3, Vec3)> {
+    line_world_to_camera_space_clipped(width, height, camera, v1, v2).map(|(v1, v2)| {
+        (
+            vertex_camera_to_screen_space(width, height, camera, v1),
+            vertex_camera_to_screen_space(width, height, camera, v2),
+        )
+    })
+}
+
+pub fn line_world_to_camera_space_clipped(
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    v1: Vec3,
+    v2: Vec3,
+) -> Option<(Vec3, Vec3)> {
+    let mut v1 = vertex_world_to_camera_space(camera, v1);
+    let mut v2 = vertex_world_to_camera_space(camera, v2);
+
+    // Normals and offsets of the frustum planes, where points inside have a
+    // positive distance. The side planes follow `vertex_camera_to_clip_space`.
+    let fov_scale = 1.0 / (camera.fov / 2.0).tan();
+    let x_scale = fov_scale * height as f32 / width as f32;
+    let planes = [
+        (Vec3::z(1.0), -camera.nearz),
+        (Vec3::z(-1.0), camera.farz),
+        (Vec3::new(x_scale, 0.0, 1.0), 0.0),
+        (Vec3::new(-x_scale, 0.0, 1.0), 0.0),
+        (Vec3::new(0.0, fov_scale, 1.0), 0.0),
+        (Vec3::new(0.0, -fov_scale, 1.0), 0.0),
+    ];
+    for (normal, offset) in planes {
+        let d1 = normal.dot(v1) + offset;
+        let d2 = normal.dot(v2) + offset;
+        if d1 < 0.0 && d2 < 0.0 {
+            return None;
+        }
+        if d1 < 0.0 {
+            v1 = v1.lerp(v2, d1 / (d1 - d2));
+        } else if d2 < 0.0 {
+            v2 = v2.lerp(v1, d2 / (d2 - d1));
+        }
+    }
+    Some((v1, v2))
+}
+
 pub fn vertex_world_to_screen_space_clipped(
     width: usize,
     height: usize,
@@ -159,6 +245,26 @@ pub fn vertex_world_to_screen_space(width: usize, height: usize, camera: &Camera
     )
 }
 
+/// World space ray from the camera through the screen position `x`, `y` in pixels,
+/// the inverse of `vertex_world_to_screen_space`. Use the pixel center, `x + 0.5`,
+/// to pick a pixel.
+pub fn screen_to_world_ray(width: usize, height: usize, camera: &Camera, x: f32, y: f32) -> Ray {
+    let fov_scale = 1.0 / (camera.fov / 2.0).tan();
+    let proj = Vec2::new(x / width as f32 * 2.0 - 1.0, 1.0 - y / height as f32 * 2.0);
+    let camera_space = Vec3::new(
+        proj.x * width as f32 / height as f32 / fov_scale,
+        proj.y / fov_scale,
+        1.0,
+    );
+    Ray::new(
+        camera.translation,
+        camera_space
+            .rotate_x(camera.pitch)
+            .rotate_y(camera.yaw)
+            .normalize(),
+    )
+}
+
 pub fn vertex_camera_to_screen_space(
     width: usize,
     height: usize,
@@ -1,5 +1,5 @@
 use crate::math::mat::Mat4;
-use crate::math::vec::Vec4;
+use crate::math::vec::{Vec3, Vec4};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -51,6 +51,15 @@ impl Quat {
         Self(Vec4::new(0.0, 0.0, s, c))
     }
 
+    /// Rotation order matches `transform_vertex`: roll, then yaw, then pitch.
+    #[inline]
+    #[must_use]
+    pub fn from_pitch_yaw_roll(pitch_yaw_roll: Vec3) -> Self {
+        Self::from_rotation_x(pitch_yaw_roll.x)
+            .mul_quat(Self::from_rotation_y(pitch_yaw_roll.y))
+            .mul_quat(Self::from_rotation_z(pitch_yaw_roll.z))
+    }
+
     #[must_use]
     pub fn mul_quat(self, rhs: Self) -> Self {
         Self(Vec4::new(
@@ -61,6 +70,35 @@ impl Quat {
         ))
     }
 
+    #[inline]
+    #[must_use]
+    pub fn normalize(self) -> Self {
+        Self(self.0 / self.length())
+    }
+
+    // https://en.wikipedia.org/wiki/Slerp
+    #[must_use]
+    pub fn slerp(self, mut end: Self, t: f32) -> Self {
+        let mut dot = self.dot(end.0);
+        // Take the shortest path.
+        if dot < 0.0 {
+            end = Self(end.0 * -1.0);
+            dot = -dot;
+        }
+
+        // Nearly parallel quaternions would divide by ~0, so fall back to a
+        // normalized lerp.
+        if dot > 0.9995 {
+            return Self(self.0 + (end.0 - self.0) * t).normalize();
+        }
+
+        let theta = libm::acosf(dot);
+        let sin_theta = libm::sinf(theta);
+        let s0 = libm::sinf((1.0 - t) * theta) / sin_theta;
+        let s1 = libm::sinf(t * theta) / sin_theta;
+        Self(self.0 * s0 + end.0 * s1)
+    }
+
     #[must_use]
     #[rustfmt::skip]
     pub fn compute_mat4(self) -> Mat4 {
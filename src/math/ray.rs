@@ -0,0 +1,82 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    // Not necessarily normalized. Distances along the ray are in units of `dir`.
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Self { origin, dir }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// The ray in the space that `matrix` transforms into. Distances along the
+    /// ray are the same in both spaces, so hits can be compared across models.
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        Self {
+            origin: matrix.mult_vec4(self.origin.extend(1.0)).reduce(),
+            dir: matrix.mult_vec4(self.dir.extend(0.0)).reduce(),
+        }
+    }
+}
+
+/// Distances along `ray` where it enters and leaves the box from `min` to `max`,
+/// using the slab test. The entry is negative if the ray starts inside the box.
+pub fn ray_aabb(ray: &Ray, min: Vec3, max: Vec3) -> Option<(f32, f32)> {
+    let mut near = f32::NEG_INFINITY;
+    let mut far = f32::INFINITY;
+    for (origin, dir, min, max) in [
+        (ray.origin.x, ray.dir.x, min.x, max.x),
+        (ray.origin.y, ray.dir.y, min.y, max.y),
+        (ray.origin.z, ray.dir.z, min.z, max.z),
+    ] {
+        if dir == 0.0 {
+            // Parallel to the slab, so the ray is either always or never in it.
+            if origin < min || origin > max {
+                return None;
+            }
+            continue;
+        }
+        let (t1, t2) = ((min - origin) / dir, (max - origin) / dir);
+        near = near.max(t1.min(t2));
+        far = far.min(t1.max(t2));
+    }
+    (near <= far && far >= 0.0).then_some((near, far))
+}
+
+/// Distance along `ray` to the triangle and the barycentric weights of the hit,
+/// using Möller–Trumbore. Both sides of the triangle are hit.
+///
+/// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+pub fn ray_triangle(ray: &Ray, v1: Vec3, v2: Vec3, v3: Vec3) -> Option<(f32, Vec3)> {
+    const EPSILON: f32 = 1e-7;
+
+    let edge1 = v2 - v1;
+    let edge2 = v3 - v1;
+    let p = ray.dir.cross(edge2);
+    let det = edge1.dot(p);
+    if libm::fabsf(det) < EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - v1;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_det;
+    (t > EPSILON).then_some((t, Vec3::new(1.0 - u - v, u, v)))
+}
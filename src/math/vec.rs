@@ -322,6 +322,12 @@ macro_rules! vec3 {
                 self.x + self.y + self.z
             }
 
+            #[inline]
+            #[must_use]
+            pub fn lerp(self, rhs: Self, t: $prim) -> Self {
+                self + (rhs - self) * t
+            }
+
             #[inline]
             #[must_use]
             pub fn rotate_x(self, angle: $prim) -> Self {
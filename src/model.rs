@@ -1,15 +1,228 @@
-use crate::{camera::Camera, math::*};
+use crate::{animation::Skeleton, camera::Camera, fog::Fog, math::*, shadow::ShadowMap};
 use rast::tint::*;
 
+pub mod util;
+
 #[derive(Default)]
 pub struct Model {
     pub faces: Vec<usize>,
-    // (uv index, texture index)
-    pub face_textures: Vec<(usize, usize)>,
+    // Uv index for each index in `faces`. Empty if the model has no uvs.
+    pub face_uvs: Vec<usize>,
+    // Material index for each triangle. Empty if the model has no materials.
+    pub face_materials: Vec<usize>,
 
     pub verts: Vec<Vec3>,
+    // Normal for each vertex. Empty if the model has no normals.
+    pub normals: Vec<Vec3>,
+    // Tangent for each vertex with the bitangent sign in `w`. Empty if the model
+    // has no tangents.
+    pub tangents: Vec<Vec4>,
     pub uvs: Vec<Vec2>,
-    pub textures: Vec<(usize, usize, Vec<Srgb>)>,
+    pub materials: Vec<Material>,
+
+    pub morph_targets: Vec<MorphTarget>,
+
+    // Joint indices and weights for each vertex. Empty if the model is not skinned.
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub skeleton: Option<Skeleton>,
+}
+
+impl Model {
+    pub fn face_material(&self, face: usize) -> Option<&Material> {
+        self.face_materials
+            .get(face)
+            .map(|material| &self.materials[*material])
+    }
+}
+
+// Blend shape, added to the vertices scaled by its weight.
+#[derive(Debug, Clone, Default)]
+pub struct MorphTarget {
+    pub name: String,
+    // Delta for each vertex.
+    pub position_deltas: Vec<Vec3>,
+    // Delta for each vertex normal. Empty if the target does not change normals.
+    pub normal_deltas: Vec<Vec3>,
+}
+
+#[derive(Clone)]
+pub struct Material {
+    // Used when there is no texture, or the model has no uvs.
+    pub base_color: LinearRgb,
+    pub texture: Option<(usize, usize, Vec<Srgb>)>,
+    pub sampler: rast::Sampler,
+    pub blend_mode: rast::BlendMode,
+    pub double_sided: bool,
+    // Fragments with an alpha below the cutoff are discarded.
+    pub alpha_cutoff: Option<f32>,
+    // Multiplies the alpha of blended materials.
+    pub opacity: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: LinearRgb::from_rgb(1.0, 1.0, 1.0),
+            texture: None,
+            sampler: rast::Sampler::Bilinear,
+            blend_mode: rast::BlendMode::None,
+            double_sided: true,
+            alpha_cutoff: None,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl Material {
+    pub fn textured(texture: (usize, usize, Vec<Srgb>)) -> Self {
+        Self {
+            texture: Some(texture),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_blended(&self) -> bool {
+        !matches!(self.blend_mode, rast::BlendMode::None)
+    }
+
+    // Color and alpha of the material at `uv`, or the base color if there is no
+    // uv.
+    pub fn shade(&self, uv: Option<Vec2>) -> (Srgb, f32) {
+        let color = match (&self.texture, uv) {
+            (Some(texture), Some(uv)) => crate::raster::sample_texture(
+                texture,
+                uv,
+                matches!(self.sampler, rast::Sampler::Bilinear),
+            ),
+            _ => self.base_color.to_srgb(),
+        };
+        (color, color.a as f32 / 255.0 * self.opacity)
+    }
+}
+
+// Replaces the shading of every model drawn with a `DrawPass`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    Shaded,
+    WireframeOverShaded,
+    Wireframe,
+    // Linear view space depth between the near and far planes, white is near.
+    Depth,
+    // World space normals mapped to colors. Faces of models without normals, or
+    // with deformed vertices, use the face normal.
+    Normals,
+    UvChecker,
+    // Number of fragments rasterized for each pixel, including the fragments that
+    // fail the depth test. Counted in the red channel of the frame by every pass
+    // and resolved once per frame by `resolve_overdraw`.
+    Overdraw,
+}
+
+impl DebugView {
+    pub const ALL: [Self; 7] = [
+        Self::Shaded,
+        Self::WireframeOverShaded,
+        Self::Wireframe,
+        Self::Depth,
+        Self::Normals,
+        Self::UvChecker,
+        Self::Overdraw,
+    ];
+
+    // Whether the view needs `FaceDebug`.
+    fn needs_face_data(self) -> bool {
+        matches!(self, Self::Depth | Self::Normals)
+    }
+}
+
+// Frame state shared by the model draw functions.
+//
+// Alpha blended triangles are queued while drawing and drawn back to front by
+// `flush` after the opaque geometry, with depth testing but no depth writes.
+#[derive(Default)]
+pub struct DrawPass<'a> {
+    // Opaque and alpha tested fragments are darkened by the shadow map.
+    pub shadow_map: Option<&'a ShadowMap>,
+    // Fogs fragments as they are drawn, see `fog::apply_fog` for a post pass.
+    pub fog: Option<Fog>,
+    pub debug_view: DebugView,
+    transparent: Vec<TransparentTriangle<'a>>,
+}
+
+struct TransparentTriangle<'a> {
+    // Screen space
+    v1: Vec3,
+    v2: Vec3,
+    v3: Vec3,
+    // View space
+    depths: (f32, f32, f32),
+    uvs: Option<(Vec2, Vec2, Vec2)>,
+    material: &'a Material,
+}
+
+impl<'a> DrawPass<'a> {
+    pub fn flush(
+        &mut self,
+        frame_buffer: &mut [Srgb],
+        zbuffer: &[f32],
+        width: usize,
+        height: usize,
+    ) {
+        debug_assert_eq!(frame_buffer.len(), zbuffer.len());
+
+        // Both depth conventions increase away from the camera.
+        self.transparent.sort_by(|a, b| {
+            let a = a.v1.z + a.v2.z + a.v3.z;
+            let b = b.v1.z + b.v2.z + b.v3.z;
+            b.total_cmp(&a)
+        });
+
+        for triangle in self.transparent.drain(..) {
+            crate::raster::rast_triangle_fragments(
+                width,
+                height,
+                triangle.v1,
+                triangle.v2,
+                triangle.v3,
+                |x, y, z, weights| {
+                    let index = y * width + x;
+                    if z >= zbuffer[index] {
+                        return;
+                    }
+                    let uv = triangle
+                        .uvs
+                        .map(|(uv1, uv2, uv3)| uv1 * weights.x + uv2 * weights.y + uv3 * weights.z);
+                    let (mut color, alpha) = triangle.material.shade(uv);
+                    if let Some(fog) = &self.fog {
+                        color = fog.apply(color, interpolate_depth(triangle.depths, weights));
+                    }
+                    frame_buffer[index] =
+                        crate::raster::blend_srgb(frame_buffer[index], color, alpha);
+                },
+            );
+        }
+    }
+}
+
+/// Replaces the fragment counts drawn in `DebugView::Overdraw` with colors. The
+/// frame must be cleared to black before drawing.
+pub fn resolve_overdraw(frame_buffer: &mut [Srgb]) {
+    for pixel in frame_buffer.iter_mut() {
+        *pixel = overdraw_color(pixel.r);
+    }
+}
+
+// Black for no fragments, then blue, green, yellow and red from 4 fragments up.
+fn overdraw_color(count: u8) -> Srgb {
+    match count {
+        0 => Srgb::from_rgb(0, 0, 0),
+        1 => Srgb::from_rgb(0, 0, 255),
+        2 => Srgb::from_rgb(0, 255, 0),
+        3 => Srgb::from_rgb(255, 255, 0),
+        _ => Srgb::from_rgb(255, 0, 0),
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -20,10 +233,14 @@ pub struct Obb {
 
 pub fn compute_obb(model: &Model) -> Obb {
     assert_model(model);
+    compute_obb_verts(&model.verts)
+}
 
-    let (minx, maxx) = min_max(model, |v| v.x);
-    let (miny, maxy) = min_max(model, |v| v.y);
-    let (minz, maxz) = min_max(model, |v| v.z);
+/// Bounds of `verts`, e.g. a model's vertices after skinning or morphing.
+pub fn compute_obb_verts(verts: &[Vec3]) -> Obb {
+    let (minx, maxx) = min_max(verts, |v| v.x);
+    let (miny, maxy) = min_max(verts, |v| v.y);
+    let (minz, maxz) = min_max(verts, |v| v.z);
 
     Obb {
         min: Vec3::new(minx, miny, minz),
@@ -31,17 +248,9 @@ pub fn compute_obb(model: &Model) -> Obb {
     }
 }
 
-fn min_max(model: &Model, f: impl Fn(&Vec3) -> f32) -> (f32, f32) {
-    let min = model
-        .verts
-        .iter()
-        .min_by(|a, b| f(a).total_cmp(&f(b)))
-        .unwrap();
-    let max = model
-        .verts
-        .iter()
-        .max_by(|a, b| f(a).total_cmp(&f(b)))
-        .unwrap();
+fn min_max(verts: &[Vec3], f: impl Fn(&Vec3) -> f32) -> (f32, f32) {
+    let min = verts.iter().min_by(|a, b| f(a).total_cmp(&f(b))).unwrap();
+    let max = verts.iter().max_by(|a, b| f(a).total_cmp(&f(b))).unwrap();
     (f(min), f(max))
 }
 
@@ -66,6 +275,37 @@ pub fn obb_visible(
     false
 }
 
+pub fn obb_visible_matrix(
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    obb: Obb,
+    model_matrix: Mat4,
+) -> bool {
+    let view_proj_matrix = compute_perspective_proj_matrix(camera, width, height).mult_mat4(
+        &compute_view_matrix(camera.translation, camera.yaw, camera.pitch),
+    );
+    obb_visible_view_proj(&view_proj_matrix, obb, model_matrix)
+}
+
+/// Like `obb_visible_matrix`, with the camera's view projection matrix computed
+/// up front, e.g. once for every instance of a model.
+pub fn obb_visible_view_proj(view_proj_matrix: &Mat4, obb: Obb, model_matrix: Mat4) -> bool {
+    let corners =
+        obb_corners_matrix(obb, model_matrix).map(|v| view_proj_matrix.mult_vec4(v.extend(1.0)));
+
+    // Unlike `obb_visible`, a box that covers the screen without any of its corners
+    // being visible is not culled. The box is only culled if every corner is
+    // outside of the same frustum plane.
+    let outside = |plane: fn(Vec4) -> bool| corners.iter().all(|v| plane(*v));
+    !(outside(|v| v.x < -v.w)
+        || outside(|v| v.x > v.w)
+        || outside(|v| v.y < -v.w)
+        || outside(|v| v.y > v.w)
+        || outside(|v| v.z < 0.0)
+        || outside(|v| v.z > v.w))
+}
+
 pub fn debug_draw_obb(
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
@@ -76,9 +316,63 @@ pub fn debug_draw_obb(
     translation: Vec3,
     pitch_yaw_roll: Vec3,
     color: Srgb,
+) {
+    debug_draw_obb_corners(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        obb_corners(obb, translation, pitch_yaw_roll),
+        color,
+    );
+}
+
+pub fn debug_draw_obb_matrix(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    obb: Obb,
+    model_matrix: Mat4,
+    color: Srgb,
+) {
+    // Unlike `debug_draw_obb`, the edges have the perspective depth of the models
+    // drawn with matrices, which is what fog expects.
+    let view_proj_matrix = crate::hiz::view_proj_matrix(camera, width, height);
+    let corners = obb_corners_matrix(obb, model_matrix);
+    #[rustfmt::skip]
+    let edges = [
+        (0, 1), (1, 2), (2, 3),
+        (3, 0), (4, 5), (5, 6),
+        (6, 7), (7, 4), (0, 4),
+        (1, 5), (2, 6), (3, 7),
+    ];
+    for (i1, i2) in edges.into_iter() {
+        crate::raster::rast_line_matrix(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            &view_proj_matrix,
+            corners[i1],
+            corners[i2],
+            color,
+        );
+    }
+}
+
+fn debug_draw_obb_corners(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    corners: [Vec3; 8],
+    color: Srgb,
 ) {
     debug_assert_eq!(frame_buffer.len(), zbuffer.len());
-    let corners = obb_corners(obb, translation, pitch_yaw_roll);
     let vertices = [
         corners[0], corners[1], corners[2], corners[0], corners[2], corners[3], corners[5],
         corners[4], corners[7], corners[5], corners[7], corners[6], corners[4], corners[0],
@@ -118,6 +412,14 @@ pub fn debug_draw_obb(
 }
 
 pub fn obb_corners(obb: Obb, translation: Vec3, pitch_yaw_roll: Vec3) -> [Vec3; 8] {
+    local_obb_corners(obb).map(|v| crate::math::transform_vertex(translation, pitch_yaw_roll, v))
+}
+
+pub fn obb_corners_matrix(obb: Obb, model_matrix: Mat4) -> [Vec3; 8] {
+    local_obb_corners(obb).map(|v| model_matrix.mult_vec4(v.extend(1.0)).reduce())
+}
+
+fn local_obb_corners(obb: Obb) -> [Vec3; 8] {
     assert_obb(obb);
     let [minx, miny, minz] = obb.min.to_array();
     let [maxx, maxy, maxz] = obb.max.to_array();
@@ -131,19 +433,23 @@ pub fn obb_corners(obb: Obb, translation: Vec3, pitch_yaw_roll: Vec3) -> [Vec3;
         Vec3::new(maxx, maxy, maxz),
         Vec3::new(minx, maxy, maxz),
     ]
-    .map(|v| crate::math::transform_vertex(translation, pitch_yaw_roll, v))
 }
 
-pub fn draw_model(
+pub fn draw_model<'a>(
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
     width: usize,
     height: usize,
     camera: &Camera,
-    model: &Model,
+    model: &'a Model,
     translation: Vec3,
     pitch_yaw_roll: Vec3,
+    pass: Option<&mut DrawPass<'a>>,
 ) {
+    let flush = pass.is_none();
+    let mut local = DrawPass::default();
+    let pass = pass.unwrap_or(&mut local);
+
     draw_model_inner(
         frame_buffer,
         zbuffer,
@@ -154,19 +460,29 @@ pub fn draw_model(
         translation,
         pitch_yaw_roll,
         false,
+        pass,
     );
+
+    if flush {
+        local.flush(frame_buffer, zbuffer, width, height);
+    }
 }
 
-pub fn draw_model_backface_culled(
+pub fn draw_model_backface_culled<'a>(
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
     width: usize,
     height: usize,
     camera: &Camera,
-    model: &Model,
+    model: &'a Model,
     translation: Vec3,
     pitch_yaw_roll: Vec3,
+    pass: Option<&mut DrawPass<'a>>,
 ) {
+    let flush = pass.is_none();
+    let mut local = DrawPass::default();
+    let pass = pass.unwrap_or(&mut local);
+
     draw_model_inner(
         frame_buffer,
         zbuffer,
@@ -177,61 +493,261 @@ pub fn draw_model_backface_culled(
         translation,
         pitch_yaw_roll,
         true,
+        pass,
     );
+
+    if flush {
+        local.flush(frame_buffer, zbuffer, width, height);
+    }
 }
 
-pub fn draw_model_matrix(
+// The draw functions queue blended faces into `pass` if provided, so that the
+// caller can sort them with the rest of the frame. Otherwise they are sorted and
+// drawn per model.
+
+pub fn draw_model_matrix<'a>(
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
     width: usize,
     height: usize,
     camera: &Camera,
-    model: &Model,
+    model: &'a Model,
     model_matrix: Mat4,
+    pass: Option<&mut DrawPass<'a>>,
 ) {
+    let flush = pass.is_none();
+    let mut local = DrawPass::default();
+    let pass = pass.unwrap_or(&mut local);
+
     draw_model_inner_matrix(
         frame_buffer,
         zbuffer,
         width,
         height,
         camera,
+        &compute_view_matrix(camera.translation, camera.yaw, camera.pitch),
+        &compute_perspective_proj_matrix(camera, width, height),
         model,
+        &model.verts,
         model_matrix,
+        None,
+        None,
         false,
+        pass,
     );
+
+    if flush {
+        local.flush(frame_buffer, zbuffer, width, height);
+    }
 }
 
-fn draw_model_inner_matrix(
+/// Draws every face of `model` with `material` instead of the model's own materials.
+pub fn draw_model_matrix_material<'a>(
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
     width: usize,
     height: usize,
     camera: &Camera,
-    model: &Model,
+    model: &'a Model,
     model_matrix: Mat4,
-    backface: bool,
+    material: &'a Material,
+    pass: Option<&mut DrawPass<'a>>,
 ) {
-    assert_model(model);
-    debug_assert_eq!(frame_buffer.len(), zbuffer.len());
+    let flush = pass.is_none();
+    let mut local = DrawPass::default();
+    let pass = pass.unwrap_or(&mut local);
 
-    if model.textures.is_empty() {
-        todo!();
-        // draw_model_inner_no_textures(
-        //     frame_buffer,
-        //     zbuffer,
-        //     width,
-        //     height,
-        //     camera,
-        //     model,
-        //     translation,
-        //     pitch_yaw_roll,
-        //     backface,
-        // );
-        // return;
+    draw_model_inner_matrix(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        &compute_view_matrix(camera.translation, camera.yaw, camera.pitch),
+        &compute_perspective_proj_matrix(camera, width, height),
+        model,
+        &model.verts,
+        model_matrix,
+        Some(material),
+        None,
+        false,
+        pass,
+    );
+
+    if flush {
+        local.flush(frame_buffer, zbuffer, width, height);
+    }
+}
+
+/// Draws `model` with `verts` in place of the model's own vertices, e.g. after
+/// skinning or morphing. `material` overrides the model's materials if provided.
+pub fn draw_model_matrix_deformed<'a>(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    model: &'a Model,
+    verts: &[Vec3],
+    model_matrix: Mat4,
+    material: Option<&'a Material>,
+    pass: Option<&mut DrawPass<'a>>,
+) {
+    let flush = pass.is_none();
+    let mut local = DrawPass::default();
+    let pass = pass.unwrap_or(&mut local);
+
+    draw_model_inner_matrix(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        &compute_view_matrix(camera.translation, camera.yaw, camera.pitch),
+        &compute_perspective_proj_matrix(camera, width, height),
+        model,
+        verts,
+        model_matrix,
+        material,
+        None,
+        false,
+        pass,
+    );
+
+    if flush {
+        local.flush(frame_buffer, zbuffer, width, height);
+    }
+}
+
+// Vertex deformation is done on a copy of the model's vertices so that the source
+// mesh is never modified. Morph targets are applied before skinning.
+
+/// Adds each morph target's deltas, scaled by its weight in `weights`, to
+/// `verts` and `normals`. `normals` may be empty if the model has no normals.
+pub fn morph_vertices(model: &Model, weights: &[f32], verts: &mut [Vec3], normals: &mut [Vec3]) {
+    debug_assert_eq!(weights.len(), model.morph_targets.len());
+    debug_assert_eq!(verts.len(), model.verts.len());
+
+    for (target, weight) in model.morph_targets.iter().zip(weights.iter()) {
+        if *weight == 0.0 {
+            continue;
+        }
+        for (v, delta) in verts.iter_mut().zip(target.position_deltas.iter()) {
+            *v += *delta * *weight;
+        }
+        for (n, delta) in normals.iter_mut().zip(target.normal_deltas.iter()) {
+            *n += *delta * *weight;
+        }
+    }
+
+    if !normals.is_empty() {
+        for n in normals.iter_mut() {
+            *n = n.normalize_or_zero();
+        }
+    }
+}
+
+/// Linear blend skinning of `verts`, which should start as the model's vertices
+/// (or morphed vertices).
+pub fn skin_vertices(model: &Model, skinning_matrices: &[Mat4], verts: &mut [Vec3]) {
+    debug_assert_eq!(model.joints.len(), verts.len());
+    debug_assert_eq!(model.weights.len(), verts.len());
+
+    for ((v, joints), weights) in verts
+        .iter_mut()
+        .zip(model.joints.iter())
+        .zip(model.weights.iter())
+    {
+        let rest = v.extend(1.0);
+        let mut skinned = Vec3::ZERO;
+        for (joint, weight) in joints.iter().zip(weights.iter()) {
+            if *weight != 0.0 {
+                skinned += skinning_matrices[*joint as usize].mult_vec4(rest).reduce() * *weight;
+            }
+        }
+        *v = skinned;
+    }
+}
+
+/// Draws `model` once for each matrix in `instances`.
+///
+/// `obb` is the model's bounding box from `compute_obb`, which callers keep
+/// rather than rescanning the vertices every frame. Each instance is culled
+/// against the frustum individually, and the camera matrices are computed once
+/// and shared by every instance.
+/// `tints` must be the same length as `instances` and replaces the vertex colors
+/// of models without materials.
+pub fn draw_model_instanced<'a>(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    model: &'a Model,
+    obb: Obb,
+    instances: &[Mat4],
+    tints: Option<&[LinearRgb]>,
+    pass: Option<&mut DrawPass<'a>>,
+) {
+    if let Some(tints) = tints {
+        assert_eq!(tints.len(), instances.len());
     }
 
     let view_matrix = compute_view_matrix(camera.translation, camera.yaw, camera.pitch);
     let proj_matrix = compute_perspective_proj_matrix(camera, width, height);
+    let view_proj_matrix = proj_matrix.mult_mat4(&view_matrix);
+
+    let flush = pass.is_none();
+    let mut local = DrawPass::default();
+    let pass = pass.unwrap_or(&mut local);
+
+    for (i, model_matrix) in instances.iter().enumerate() {
+        if !obb_visible_view_proj(&view_proj_matrix, obb, *model_matrix) {
+            continue;
+        }
+
+        draw_model_inner_matrix(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            camera,
+            &view_matrix,
+            &proj_matrix,
+            model,
+            &model.verts,
+            *model_matrix,
+            None,
+            tints.map(|tints| tints[i]),
+            false,
+            pass,
+        );
+    }
+
+    if flush {
+        local.flush(frame_buffer, zbuffer, width, height);
+    }
+}
+
+fn draw_model_inner_matrix<'a>(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    view_matrix: &Mat4,
+    proj_matrix: &Mat4,
+    model: &'a Model,
+    verts: &[Vec3],
+    model_matrix: Mat4,
+    material_override: Option<&'a Material>,
+    tint: Option<LinearRgb>,
+    backface: bool,
+    pass: &mut DrawPass<'a>,
+) {
+    assert_model(model);
+    debug_assert_eq!(verts.len(), model.verts.len());
+    debug_assert_eq!(frame_buffer.len(), zbuffer.len());
 
     fn camera_to_screen_space(width: usize, height: usize, mut v: Vec4) -> Vec3 {
         v.x /= v.w;
@@ -247,18 +763,19 @@ fn draw_model_inner_matrix(
     let model_to_view_matrix = view_matrix.mult_mat4(&model_matrix);
     let model_to_proj_matrix = proj_matrix.mult_mat4(&model_to_view_matrix);
 
-    for (face, face_textures) in model.faces.chunks(3).zip(model.face_textures.chunks(3)) {
-        let mv1 = model.verts[face[0]].extend(1.0);
-        let mv2 = model.verts[face[1]].extend(1.0);
-        let mv3 = model.verts[face[2]].extend(1.0);
+    for (i, face) in model.faces.chunks(3).enumerate() {
+        let mv1 = verts[face[0]].extend(1.0);
+        let mv2 = verts[face[1]].extend(1.0);
+        let mv3 = verts[face[2]].extend(1.0);
 
         let v1 = model_to_view_matrix.mult_vec4(mv1).reduce();
         let v2 = model_to_view_matrix.mult_vec4(mv2).reduce();
         let v3 = model_to_view_matrix.mult_vec4(mv3).reduce();
 
-        let v1z = v1.z;
-        let v2z = v2.z;
-        let v3z = v3.z;
+        // View space looks down -z.
+        let v1z = -v1.z;
+        let v2z = -v2.z;
+        let v3z = -v3.z;
 
         if v1z <= camera.nearz
             || v1z >= camera.farz
@@ -270,10 +787,14 @@ fn draw_model_inner_matrix(
             continue;
         }
 
-        if backface {
+        let material = material_override.or_else(|| model.face_material(i));
+        if backface || material.is_some_and(|material| !material.double_sided) {
             // https://en.wikipedia.org/wiki/Back-face_culling#Implementation
+            //
+            // NOTE: The winding is flipped compared to `draw_model_inner` because
+            // view space looks down -z.
             let normal = (v3 - v1).cross(v2 - v1);
-            if v1.dot(normal) < 0.0 {
+            if v1.dot(normal) > 0.0 {
                 continue;
             }
         }
@@ -286,83 +807,64 @@ fn draw_model_inner_matrix(
         let v2 = camera_to_screen_space(width, height, v2);
         let v3 = camera_to_screen_space(width, height, v3);
 
-        debug_assert_eq!(face_textures[0].1, face_textures[1].1);
-        debug_assert_eq!(face_textures[2].1, face_textures[1].1);
+        let world = (pass.shadow_map.is_some() || pass.debug_view.needs_face_data()).then(|| {
+            (
+                model_matrix.mult_vec4(mv1).reduce(),
+                model_matrix.mult_vec4(mv2).reduce(),
+                model_matrix.mult_vec4(mv3).reduce(),
+            )
+        });
+        let debug = pass.debug_view.needs_face_data().then(|| {
+            // Deformed vertices don't match the model's normals.
+            let deformed = !core::ptr::eq(verts, model.verts.as_slice());
+            face_debug(model, face, world.unwrap(), camera, deformed, |n| {
+                model_matrix.mult_vec4(n.extend(0.0)).reduce()
+            })
+        });
 
-        let texture = &model.textures[face_textures[0].1];
-        let (uv, _) = face_textures[0];
-        let uv1 = model.uvs[uv];
-        let (uv, _) = face_textures[1];
-        let uv2 = model.uvs[uv];
-        let (uv, _) = face_textures[2];
-        let uv3 = model.uvs[uv];
-
-        rast::rast_triangle_checked(
+        rast_face(
             frame_buffer,
             zbuffer,
             width,
             height,
-            libm::floorf(v1.x) as i32,
-            libm::floorf(v1.y) as i32,
-            v1.z,
-            libm::floorf(v2.x) as i32,
-            libm::floorf(v2.y) as i32,
-            v2.z,
-            libm::floorf(v3.x) as i32,
-            libm::floorf(v3.y) as i32,
-            v3.z,
-            (uv1.x, uv1.y),
-            (uv2.x, uv2.y),
-            (uv3.x, uv3.y),
-            rast::TextureShader {
-                width: texture.0,
-                height: texture.1,
-                texture: texture.2.as_slice(),
-                sampler: rast::Sampler::Bilinear,
-                blend_mode: rast::BlendMode::None,
-            },
+            model,
+            i,
+            material,
+            tint,
+            (v1, v2, v3),
+            (v1z, v2z, v3z),
+            world,
+            debug,
+            pass,
         );
     }
 }
 
-fn draw_model_inner(
+fn draw_model_inner<'a>(
     frame_buffer: &mut [Srgb],
     zbuffer: &mut [f32],
     width: usize,
     height: usize,
     camera: &Camera,
-    model: &Model,
+    model: &'a Model,
     translation: Vec3,
     pitch_yaw_roll: Vec3,
     backface: bool,
+    pass: &mut DrawPass<'a>,
 ) {
     assert_model(model);
     debug_assert_eq!(frame_buffer.len(), zbuffer.len());
 
-    if model.textures.is_empty() {
-        draw_model_inner_no_textures(
-            frame_buffer,
-            zbuffer,
-            width,
-            height,
-            camera,
-            model,
-            translation,
-            pitch_yaw_roll,
-            backface,
-        );
-        return;
-    }
-
-    for (face, face_textures) in model.faces.chunks(3).zip(model.face_textures.chunks(3)) {
-        let v1 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[0]]);
-        let v2 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[1]]);
-        let v3 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[2]]);
+    for (i, face) in model.faces.chunks(3).enumerate() {
+        let p1 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[0]]);
+        let p2 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[1]]);
+        let p3 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[2]]);
 
         if let Some((v1, v2, v3)) =
-            crate::math::triangle_world_to_camera_space_clipped(camera, v1, v2, v3)
+            crate::math::triangle_world_to_camera_space_clipped(camera, p1, p2, p3)
         {
-            if backface {
+            let material = model.face_material(i);
+            if backface || material.is_some_and(|material| !material.double_sided) {
                 // https://en.wikipedia.org/wiki/Back-face_culling#Implementation
                 let normal = (v3 - v1).cross(v2 - v1);
                 if v1.dot(normal) < 0.0 {
@@ -370,31 +872,314 @@ fn draw_model_inner(
                 }
             }
 
-            debug_assert_eq!(face_textures[0].1, face_textures[1].1);
-            debug_assert_eq!(face_textures[2].1, face_textures[1].1);
-
-            let texture = &model.textures[face_textures[0].1];
-            let (uv, _) = face_textures[0];
-            let uv1 = model.uvs[uv];
-            let (uv, _) = face_textures[1];
-            let uv2 = model.uvs[uv];
-            let (uv, _) = face_textures[2];
-            let uv3 = model.uvs[uv];
+            let debug = pass.debug_view.needs_face_data().then(|| {
+                face_debug(model, face, (p1, p2, p3), camera, false, |n| {
+                    transform_vertex(Vec3::ZERO, pitch_yaw_roll, n)
+                })
+            });
 
+            let depths = (v1.z, v2.z, v3.z);
             let (v1, v2, v3) = triangle_camera_to_screen_space(width, height, camera, v1, v2, v3);
+            rast_face(
+                frame_buffer,
+                zbuffer,
+                width,
+                height,
+                model,
+                i,
+                material,
+                None,
+                (v1, v2, v3),
+                depths,
+                Some((p1, p2, p3)),
+                debug,
+                pass,
+            );
+        }
+    }
+}
+
+// Per vertex data for the debug views that need more than the screen space
+// triangle.
+struct FaceDebug {
+    // World space.
+    normals: (Vec3, Vec3, Vec3),
+    nearz: f32,
+    farz: f32,
+}
+
+fn face_debug(
+    model: &Model,
+    face: &[usize],
+    (p1, p2, p3): (Vec3, Vec3, Vec3),
+    camera: &Camera,
+    deformed: bool,
+    normal_to_world: impl Fn(Vec3) -> Vec3,
+) -> FaceDebug {
+    let normals = if model.normals.is_empty() || deformed {
+        let normal = (p2 - p1).cross(p3 - p1).normalize_or_zero();
+        (normal, normal, normal)
+    } else {
+        let normal = |v: usize| normal_to_world(model.normals[v]).normalize_or_zero();
+        (normal(face[0]), normal(face[1]), normal(face[2]))
+    };
+    FaceDebug {
+        normals,
+        nearz: camera.nearz,
+        farz: camera.farz,
+    }
+}
+
+// Rasterizes the screen space triangle for `face` in the pass's debug view.
+// `depths` are the view space distances of the vertices from the camera plane.
+// `debug` is only needed by views where `DebugView::needs_face_data` is true.
+fn rast_face<'a>(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    model: &'a Model,
+    face: usize,
+    material: Option<&'a Material>,
+    tint: Option<LinearRgb>,
+    (v1, v2, v3): (Vec3, Vec3, Vec3),
+    depths: (f32, f32, f32),
+    world: Option<(Vec3, Vec3, Vec3)>,
+    debug: Option<FaceDebug>,
+    pass: &mut DrawPass<'a>,
+) {
+    let triangle = (v1, v2, v3);
+    match pass.debug_view {
+        DebugView::Shaded => {
+            shade_face(
+                frame_buffer,
+                zbuffer,
+                width,
+                height,
+                model,
+                face,
+                material,
+                tint,
+                triangle,
+                depths,
+                world,
+                pass,
+            );
+        }
+        DebugView::WireframeOverShaded => {
+            shade_face(
+                frame_buffer,
+                zbuffer,
+                width,
+                height,
+                model,
+                face,
+                material,
+                tint,
+                triangle,
+                depths,
+                world,
+                pass,
+            );
+            draw_face_wireframe(
+                frame_buffer,
+                zbuffer,
+                width,
+                height,
+                triangle,
+                Srgb::from_rgb(0, 0, 0),
+            );
+        }
+        DebugView::Wireframe => {
+            draw_face_wireframe(
+                frame_buffer,
+                zbuffer,
+                width,
+                height,
+                triangle,
+                Srgb::from_rgb(255, 255, 255),
+            );
+        }
+        DebugView::Overdraw => {
+            crate::raster::rast_triangle_fragments(width, height, v1, v2, v3, |x, y, _, _| {
+                let count = &mut frame_buffer[y * width + x].r;
+                *count = count.saturating_add(1);
+            });
+        }
+        view @ (DebugView::Depth | DebugView::Normals | DebugView::UvChecker) => {
+            let uvs = face_uvs(model, face);
+            draw_fragment_face(
+                frame_buffer,
+                zbuffer,
+                width,
+                height,
+                triangle,
+                None,
+                None,
+                |weights| {
+                    Some(match view {
+                        DebugView::Depth => {
+                            let debug = debug.as_ref().unwrap();
+                            let depth = interpolate_depth(depths, weights);
+                            let t = (depth - debug.nearz) / (debug.farz - debug.nearz);
+                            let c = ((1.0 - t.clamp(0.0, 1.0)) * 255.0) as u8;
+                            Srgb::from_rgb(c, c, c)
+                        }
+                        DebugView::Normals => {
+                            let (n1, n2, n3) = debug.as_ref().unwrap().normals;
+                            let n = (n1 * weights.x + n2 * weights.y + n3 * weights.z)
+                                .normalize_or_zero();
+                            let c = |x: f32| ((x * 0.5 + 0.5) * 255.0) as u8;
+                            Srgb::from_rgb(c(n.x), c(n.y), c(n.z))
+                        }
+                        _ => match uvs {
+                            Some((uv1, uv2, uv3)) => {
+                                let uv = uv1 * weights.x + uv2 * weights.y + uv3 * weights.z;
+                                let cells = libm::floorf(uv.x * 8.0) + libm::floorf(uv.y * 8.0);
+                                if cells.rem_euclid(2.0) == 0.0 {
+                                    Srgb::from_rgb(230, 230, 230)
+                                } else {
+                                    Srgb::from_rgb(40, 40, 40)
+                                }
+                            }
+                            // Models without uvs stand out in magenta.
+                            None => Srgb::from_rgb(255, 0, 255),
+                        },
+                    })
+                },
+            );
+        }
+    }
+}
+
+fn draw_face_wireframe(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    (v1, v2, v3): (Vec3, Vec3, Vec3),
+    color: Srgb,
+) {
+    // Pulled towards the camera so that the lines win the depth test against
+    // their own face.
+    const BIAS: f32 = 1e-4;
+    rast::rast_triangle_wireframe_checked(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        libm::floorf(v1.x) as i32,
+        libm::floorf(v1.y) as i32,
+        v1.z - BIAS,
+        libm::floorf(v2.x) as i32,
+        libm::floorf(v2.y) as i32,
+        v2.z - BIAS,
+        libm::floorf(v3.x) as i32,
+        libm::floorf(v3.y) as i32,
+        v3.z - BIAS,
+        color,
+    );
+}
+
+// Shades the screen space triangle for `face` with the state from `material`.
+// Faces without a material are drawn with `tint`, or vertex colors if there is
+// no tint.
+//
+// Blended faces are queued into `pass`. Alpha tested faces, and every face when
+// there is a shadow map or fog, are shaded per fragment with `draw_fragment_face`.
+// `world` is only needed when there is a shadow map.
+fn shade_face<'a>(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    model: &'a Model,
+    face: usize,
+    material: Option<&'a Material>,
+    tint: Option<LinearRgb>,
+    (v1, v2, v3): (Vec3, Vec3, Vec3),
+    depths: (f32, f32, f32),
+    world: Option<(Vec3, Vec3, Vec3)>,
+    pass: &mut DrawPass<'a>,
+) {
+    let uvs = face_uvs(model, face);
+
+    if let Some(material) = material {
+        if material.is_blended() {
+            pass.transparent.push(TransparentTriangle {
+                v1,
+                v2,
+                v3,
+                depths,
+                uvs,
+                material,
+            });
+            return;
+        }
+    }
+
+    let cutoff = material.and_then(|material| material.alpha_cutoff);
+    if cutoff.is_some() || pass.shadow_map.is_some() || pass.fog.is_some() {
+        let shadow = pass.shadow_map.zip(world);
+        let fog = pass.fog.as_ref().map(|fog| (fog, depths));
+        draw_fragment_face(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            (v1, v2, v3),
+            shadow,
+            fog,
+            |weights| match material {
+                Some(material) => {
+                    let uv = uvs
+                        .map(|(uv1, uv2, uv3)| uv1 * weights.x + uv2 * weights.y + uv3 * weights.z);
+                    let (color, alpha) = material.shade(uv);
+                    cutoff.is_none_or(|cutoff| alpha >= cutoff).then_some(color)
+                }
+                None => Some(match tint {
+                    Some(tint) => tint.to_srgb(),
+                    None => Srgb::from_rgb(
+                        (weights.x * 255.0) as u8,
+                        (weights.y * 255.0) as u8,
+                        (weights.z * 255.0) as u8,
+                    ),
+                }),
+            },
+        );
+        return;
+    }
+
+    let x1 = libm::floorf(v1.x) as i32;
+    let y1 = libm::floorf(v1.y) as i32;
+    let x2 = libm::floorf(v2.x) as i32;
+    let y2 = libm::floorf(v2.y) as i32;
+    let x3 = libm::floorf(v3.x) as i32;
+    let y3 = libm::floorf(v3.y) as i32;
+
+    match (material, uvs) {
+        (
+            Some(
+                material @ Material {
+                    texture: Some(texture),
+                    ..
+                },
+            ),
+            Some((uv1, uv2, uv3)),
+        ) => {
             rast::rast_triangle_checked(
                 frame_buffer,
                 zbuffer,
                 width,
                 height,
-                libm::floorf(v1.x) as i32,
-                libm::floorf(v1.y) as i32,
+                x1,
+                y1,
                 v1.z,
-                libm::floorf(v2.x) as i32,
-                libm::floorf(v2.y) as i32,
+                x2,
+                y2,
                 v2.z,
-                libm::floorf(v3.x) as i32,
-                libm::floorf(v3.y) as i32,
+                x3,
+                y3,
                 v3.z,
                 (uv1.x, uv1.y),
                 (uv2.x, uv2.y),
@@ -403,65 +1188,99 @@ fn draw_model_inner(
                     width: texture.0,
                     height: texture.1,
                     texture: texture.2.as_slice(),
-                    sampler: rast::Sampler::Bilinear,
-                    blend_mode: rast::BlendMode::None,
+                    sampler: material.sampler,
+                    blend_mode: material.blend_mode,
                 },
             );
         }
-    }
-}
+        _ => {
+            let (c1, c2, c3) = match (material, tint) {
+                (Some(material), _) => (
+                    material.base_color,
+                    material.base_color,
+                    material.base_color,
+                ),
+                (None, Some(tint)) => (tint, tint, tint),
+                (None, None) => (
+                    LinearRgb::from_rgb(1.0, 0.0, 0.0),
+                    LinearRgb::from_rgb(0.0, 1.0, 0.0),
+                    LinearRgb::from_rgb(0.0, 0.0, 1.0),
+                ),
+            };
 
-fn draw_model_inner_no_textures(
-    frame_buffer: &mut [Srgb],
-    zbuffer: &mut [f32],
-    width: usize,
-    height: usize,
-    camera: &Camera,
-    model: &Model,
-    translation: Vec3,
-    pitch_yaw_roll: Vec3,
-    backface: bool,
-) {
-    for face in model.faces.chunks(3) {
-        let v1 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[0]]);
-        let v2 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[1]]);
-        let v3 = transform_vertex(translation, pitch_yaw_roll, model.verts[face[2]]);
-
-        if let Some((v1, v2, v3)) =
-            crate::math::triangle_world_to_camera_space_clipped(camera, v1, v2, v3)
-        {
-            if backface {
-                // https://en.wikipedia.org/wiki/Back-face_culling#Implementation
-                let normal = (v3 - v1).cross(v2 - v1);
-                if v1.dot(normal) < 0.0 {
-                    continue;
-                }
-            }
-
-            let (v1, v2, v3) = triangle_camera_to_screen_space(width, height, camera, v1, v2, v3);
             rast::rast_triangle_checked(
                 frame_buffer,
                 zbuffer,
                 width,
                 height,
-                libm::floorf(v1.x) as i32,
-                libm::floorf(v1.y) as i32,
+                x1,
+                y1,
                 v1.z,
-                libm::floorf(v2.x) as i32,
-                libm::floorf(v2.y) as i32,
+                x2,
+                y2,
                 v2.z,
-                libm::floorf(v3.x) as i32,
-                libm::floorf(v3.y) as i32,
+                x3,
+                y3,
                 v3.z,
-                LinearRgb::from_rgb(1.0, 0.0, 0.0),
-                LinearRgb::from_rgb(0.0, 1.0, 0.0),
-                LinearRgb::from_rgb(0.0, 0.0, 1.0),
+                c1,
+                c2,
+                c3,
                 rast::ColorShader::default(),
             );
         }
     }
 }
 
+// Depth tested and written like `rast`, but `shade` is called for every fragment
+// and can discard it by returning `None`. Fragments are darkened by the shadow
+// map at their interpolated world position, then fogged by their view space depth.
+fn draw_fragment_face(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    (v1, v2, v3): (Vec3, Vec3, Vec3),
+    shadow: Option<(&ShadowMap, (Vec3, Vec3, Vec3))>,
+    fog: Option<(&Fog, (f32, f32, f32))>,
+    shade: impl Fn(Vec3) -> Option<Srgb>,
+) {
+    crate::raster::rast_triangle_fragments(width, height, v1, v2, v3, |x, y, z, weights| {
+        let index = y * width + x;
+        if z >= zbuffer[index] {
+            return;
+        }
+        let Some(mut color) = shade(weights) else {
+            return;
+        };
+        if let Some((shadow_map, (p1, p2, p3))) = shadow {
+            let p = p1 * weights.x + p2 * weights.y + p3 * weights.z;
+            color = crate::raster::scale_srgb(color, shadow_map.light_factor(p));
+        }
+        if let Some((fog, depths)) = fog {
+            color = fog.apply(color, interpolate_depth(depths, weights));
+        }
+        frame_buffer[index] = color;
+        zbuffer[index] = z;
+    });
+}
+
+// The reciprocal of view space depth is linear in screen space, so depth is
+// interpolated through it to stay perspective correct.
+fn interpolate_depth((d1, d2, d3): (f32, f32, f32), weights: Vec3) -> f32 {
+    1.0 / (weights.x / d1 + weights.y / d2 + weights.z / d3)
+}
+
+pub fn face_uvs(model: &Model, face: usize) -> Option<(Vec2, Vec2, Vec2)> {
+    if model.face_uvs.is_empty() {
+        return None;
+    }
+    Some((
+        model.uvs[model.face_uvs[face * 3]],
+        model.uvs[model.face_uvs[face * 3 + 1]],
+        model.uvs[model.face_uvs[face * 3 + 2]],
+    ))
+}
+
 fn assert_obb(obb: Obb) {
     debug_assert!(obb.min.x <= obb.max.x);
     debug_assert!(obb.min.y <= obb.max.y);
@@ -472,12 +1291,24 @@ fn assert_model(model: &Model) {
     debug_assert!(!model.verts.is_empty());
     debug_assert!(!model.faces.is_empty());
     debug_assert!(model.faces.len() % 3 == 0);
-    debug_assert!(model.face_textures.len() % 3 == 0);
-    debug_assert!(
-        model.face_textures.is_empty() == model.uvs.is_empty()
-            && model.uvs.is_empty() == model.textures.is_empty()
-    );
-    if !model.face_textures.is_empty() {
-        debug_assert_eq!(model.faces.len(), model.face_textures.len());
+    debug_assert!(model.face_uvs.is_empty() == model.uvs.is_empty());
+    if !model.face_uvs.is_empty() {
+        debug_assert_eq!(model.faces.len(), model.face_uvs.len());
+    }
+    if !model.face_materials.is_empty() {
+        debug_assert_eq!(model.faces.len() / 3, model.face_materials.len());
+    }
+    debug_assert!(model.normals.is_empty() || model.normals.len() == model.verts.len());
+    debug_assert!(model.tangents.is_empty() || model.tangents.len() == model.verts.len());
+    for target in model.morph_targets.iter() {
+        debug_assert_eq!(target.position_deltas.len(), model.verts.len());
+        debug_assert!(
+            target.normal_deltas.is_empty() || target.normal_deltas.len() == model.verts.len()
+        );
+    }
+    debug_assert!(model.joints.len() == model.weights.len());
+    if !model.joints.is_empty() {
+        debug_assert_eq!(model.verts.len(), model.joints.len());
+        debug_assert!(model.skeleton.is_some());
     }
 }
@@ -0,0 +1,567 @@
+use super::Model;
+use crate::math::*;
+use std::collections::{HashMap, HashSet};
+
+// Mesh clean up for imported models. Vertex attributes (normals, tangents,
+// skinning and morph target deltas) are kept in sync with `verts`, and face
+// attributes (uvs and materials) with `faces`.
+
+/// Merges vertices that are within `epsilon` of each other. Merged vertices keep
+/// the attributes of the first vertex. Returns the number of removed vertices.
+pub fn weld_vertices(model: &mut Model, epsilon: f32) -> usize {
+    // Vertices are bucketed into cells of size `epsilon`.
+    assert!(
+        epsilon > 0.0 && epsilon.is_finite(),
+        "invalid weld epsilon: {epsilon}"
+    );
+    let cell = |v: Vec3| {
+        (
+            libm::floorf(v.x / epsilon) as i64,
+            libm::floorf(v.y / epsilon) as i64,
+            libm::floorf(v.z / epsilon) as i64,
+        )
+    };
+
+    let mut grid = HashMap::<(i64, i64, i64), Vec<usize>>::new();
+    let mut remap = Vec::with_capacity(model.verts.len());
+    let mut sources = Vec::new();
+    for v in model.verts.iter() {
+        let (x, y, z) = cell(*v);
+        let mut welded = None;
+        'search: for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let Some(candidates) = grid.get(&(x + dx, y + dy, z + dz)) else {
+                        continue;
+                    };
+                    if let Some(candidate) = candidates
+                        .iter()
+                        .find(|i| model.verts[sources[**i]].distance(*v) <= epsilon)
+                    {
+                        welded = Some(*candidate);
+                        break 'search;
+                    }
+                }
+            }
+        }
+
+        let index = welded.unwrap_or_else(|| {
+            sources.push(remap.len());
+            grid.entry((x, y, z)).or_default().push(sources.len() - 1);
+            sources.len() - 1
+        });
+        remap.push(index);
+    }
+
+    let removed = model.verts.len() - sources.len();
+    select_vertices(model, &sources);
+    for v in model.faces.iter_mut() {
+        *v = remap[*v];
+    }
+    removed
+}
+
+/// Removes faces that reuse a vertex or have an area below `epsilon`. Returns the
+/// number of removed faces.
+pub fn remove_degenerate_faces(model: &mut Model, epsilon: f32) -> usize {
+    let degenerate = model
+        .faces
+        .chunks(3)
+        .map(|face| is_degenerate(&model.verts, face, epsilon))
+        .collect::<Vec<_>>();
+    retain_faces(model, |face| !degenerate[face])
+}
+
+/// Removes faces that use the same vertices with the same winding as an earlier
+/// face. Faces with the opposite winding are kept, since they are the back side
+/// of the face. Returns the number of removed faces.
+pub fn remove_duplicate_faces(model: &mut Model) -> usize {
+    let mut seen = HashSet::new();
+    let duplicate = model
+        .faces
+        .chunks(3)
+        .map(|face| !seen.insert(canonical_face(face)))
+        .collect::<Vec<_>>();
+    retain_faces(model, |face| !duplicate[face])
+}
+
+/// Computes smooth vertex normals, weighted by face area. Faces that meet at an
+/// angle greater than `angle_threshold` radians are not smoothed together, and
+/// vertices on such hard edges are split.
+pub fn compute_smooth_normals(model: &mut Model, angle_threshold: f32) {
+    let face_normals = model
+        .faces
+        .chunks(3)
+        .map(|face| {
+            let (v1, v2, v3) = (
+                model.verts[face[0]],
+                model.verts[face[1]],
+                model.verts[face[2]],
+            );
+            // Length is twice the area.
+            (v2 - v1).cross(v3 - v1)
+        })
+        .collect::<Vec<_>>();
+
+    let mut adjacent = vec![Vec::new(); model.verts.len()];
+    for (i, v) in model.faces.iter().enumerate() {
+        adjacent[*v].push(i / 3);
+    }
+
+    let cos_threshold = libm::cosf(angle_threshold);
+    let corner_normals = model
+        .faces
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let normal = face_normals[i / 3].normalize_or_zero();
+            adjacent[*v]
+                .iter()
+                .map(|face| face_normals[*face])
+                .filter(|other| other.normalize_or_zero().dot(normal) >= cos_threshold)
+                .fold(Vec3::ZERO, |sum, other| sum + other)
+                .normalize_or_zero()
+        })
+        .collect::<Vec<_>>();
+
+    // Corners of a vertex that ended up with different normals get their own copy
+    // of the vertex.
+    let mut sources = (0..model.verts.len()).collect::<Vec<_>>();
+    let mut normals = vec![None; model.verts.len()];
+    let mut splits = HashMap::<usize, Vec<usize>>::new();
+    for (i, normal) in corner_normals.into_iter().enumerate() {
+        let v = model.faces[i];
+        let Some(existing) = normals[v] else {
+            normals[v] = Some(normal);
+            continue;
+        };
+        if same_normal(existing, normal) {
+            continue;
+        }
+
+        let copies = splits.entry(v).or_default();
+        if let Some(copy) = copies
+            .iter()
+            .find(|copy| normals[**copy].is_some_and(|n| same_normal(n, normal)))
+        {
+            model.faces[i] = *copy;
+            continue;
+        }
+        sources.push(v);
+        normals.push(Some(normal));
+        copies.push(sources.len() - 1);
+        model.faces[i] = sources.len() - 1;
+    }
+
+    if sources.len() != model.verts.len() {
+        select_vertices(model, &sources);
+    }
+    model.normals = normals
+        .into_iter()
+        .map(|normal| normal.unwrap_or(Vec3::ZERO))
+        .collect();
+}
+
+/// Computes a tangent for each vertex from the model's uvs, with the sign of the
+/// bitangent in `w`. Does nothing if the model has no uvs or normals.
+pub fn compute_tangents(model: &mut Model) {
+    if model.face_uvs.is_empty() || model.normals.is_empty() {
+        return;
+    }
+
+    let mut tangents = vec![Vec3::ZERO; model.verts.len()];
+    let mut bitangents = vec![Vec3::ZERO; model.verts.len()];
+    for (face, uvs) in model.faces.chunks(3).zip(model.face_uvs.chunks(3)) {
+        let (v1, v2, v3) = (
+            model.verts[face[0]],
+            model.verts[face[1]],
+            model.verts[face[2]],
+        );
+        let (uv1, uv2, uv3) = (model.uvs[uvs[0]], model.uvs[uvs[1]], model.uvs[uvs[2]]);
+        let (e1, e2) = (v2 - v1, v3 - v1);
+        let (d1, d2) = (uv2 - uv1, uv3 - uv1);
+        let det = d1.cross(d2);
+        if det == 0.0 {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (e1 * d2.y - e2 * d1.y) * r;
+        let bitangent = (e2 * d1.x - e1 * d2.x) * r;
+        for v in face {
+            tangents[*v] += tangent;
+            bitangents[*v] += bitangent;
+        }
+    }
+
+    model.tangents = tangents
+        .into_iter()
+        .zip(bitangents)
+        .zip(model.normals.iter())
+        .map(|((tangent, bitangent), normal)| {
+            // Gram-Schmidt orthogonalize against the normal.
+            let tangent = (tangent - *normal * normal.dot(tangent)).normalize_or_zero();
+            let sign = if normal.cross(tangent).dot(bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            tangent.extend(sign)
+        })
+        .collect();
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ValidationReport {
+    // Vertex or face attributes whose length does not match the model.
+    pub mismatched_attributes: Vec<&'static str>,
+    pub vertex_indices_out_of_range: usize,
+    pub uv_indices_out_of_range: usize,
+    pub material_indices_out_of_range: usize,
+    pub non_finite_verts: usize,
+
+    pub degenerate_faces: usize,
+    pub duplicate_faces: usize,
+    pub unused_verts: usize,
+    pub unnormalized_normals: usize,
+}
+
+impl ValidationReport {
+    /// Whether the model can be drawn without indexing out of bounds.
+    pub fn is_valid(&self) -> bool {
+        self.mismatched_attributes.is_empty()
+            && self.vertex_indices_out_of_range == 0
+            && self.uv_indices_out_of_range == 0
+            && self.material_indices_out_of_range == 0
+            && self.non_finite_verts == 0
+    }
+
+    /// Whether the model has problems that can be fixed with the functions in this
+    /// module.
+    pub fn is_clean(&self) -> bool {
+        self.is_valid()
+            && self.degenerate_faces == 0
+            && self.duplicate_faces == 0
+            && self.unused_verts == 0
+            && self.unnormalized_normals == 0
+    }
+}
+
+impl core::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_clean() {
+            return write!(f, "ok");
+        }
+        for attribute in self.mismatched_attributes.iter() {
+            writeln!(f, "mismatched length: {attribute}")?;
+        }
+        for (name, count) in [
+            (
+                "vertex indices out of range",
+                self.vertex_indices_out_of_range,
+            ),
+            ("uv indices out of range", self.uv_indices_out_of_range),
+            (
+                "material indices out of range",
+                self.material_indices_out_of_range,
+            ),
+            ("non finite verts", self.non_finite_verts),
+            ("degenerate faces", self.degenerate_faces),
+            ("duplicate faces", self.duplicate_faces),
+            ("unused verts", self.unused_verts),
+            ("unnormalized normals", self.unnormalized_normals),
+        ] {
+            if count > 0 {
+                writeln!(f, "{name}: {count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks `model` for problems without modifying or asserting on it.
+pub fn validate(model: &Model) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let vert_count = model.verts.len();
+
+    if model.faces.len() % 3 != 0 {
+        report.mismatched_attributes.push("faces");
+    }
+    if !model.face_uvs.is_empty() && model.face_uvs.len() != model.faces.len() {
+        report.mismatched_attributes.push("face_uvs");
+    }
+    if !model.face_materials.is_empty() && model.face_materials.len() != model.faces.len() / 3 {
+        report.mismatched_attributes.push("face_materials");
+    }
+    for (name, len) in [
+        ("normals", model.normals.len()),
+        ("tangents", model.tangents.len()),
+        ("joints", model.joints.len()),
+        ("weights", model.weights.len()),
+    ] {
+        if len != 0 && len != vert_count {
+            report.mismatched_attributes.push(name);
+        }
+    }
+    for target in model.morph_targets.iter() {
+        if target.position_deltas.len() != vert_count
+            || (!target.normal_deltas.is_empty() && target.normal_deltas.len() != vert_count)
+        {
+            report.mismatched_attributes.push("morph_targets");
+            break;
+        }
+    }
+
+    report.vertex_indices_out_of_range = model.faces.iter().filter(|v| **v >= vert_count).count();
+    report.uv_indices_out_of_range = model
+        .face_uvs
+        .iter()
+        .filter(|uv| **uv >= model.uvs.len())
+        .count();
+    report.material_indices_out_of_range = model
+        .face_materials
+        .iter()
+        .filter(|material| **material >= model.materials.len())
+        .count();
+    report.non_finite_verts = model
+        .verts
+        .iter()
+        .filter(|v| !(v.x.is_finite() && v.y.is_finite() && v.z.is_finite()))
+        .count();
+    report.unnormalized_normals = model
+        .normals
+        .iter()
+        .filter(|n| libm::fabsf(n.length() - 1.0) > 1e-3)
+        .count();
+
+    // The remaining checks index into `verts`.
+    if report.vertex_indices_out_of_range > 0 || model.faces.len() % 3 != 0 {
+        return report;
+    }
+
+    let mut seen = HashSet::new();
+    for face in model.faces.chunks(3) {
+        if is_degenerate(&model.verts, face, f32::EPSILON) {
+            report.degenerate_faces += 1;
+        }
+        if !seen.insert(canonical_face(face)) {
+            report.duplicate_faces += 1;
+        }
+    }
+
+    let mut used = vec![false; vert_count];
+    for v in model.faces.iter() {
+        used[*v] = true;
+    }
+    report.unused_verts = used.iter().filter(|used| !**used).count();
+
+    report
+}
+
+fn is_degenerate(verts: &[Vec3], face: &[usize], epsilon: f32) -> bool {
+    if face[0] == face[1] || face[1] == face[2] || face[2] == face[0] {
+        return true;
+    }
+    let (v1, v2, v3) = (verts[face[0]], verts[face[1]], verts[face[2]]);
+    (v2 - v1).cross(v3 - v1).length() / 2.0 < epsilon
+}
+
+// Rotates the face so that its smallest index comes first, keeping the winding.
+fn canonical_face(face: &[usize]) -> [usize; 3] {
+    let first = (0..3).min_by_key(|i| face[*i]).unwrap();
+    [face[first], face[(first + 1) % 3], face[(first + 2) % 3]]
+}
+
+fn same_normal(a: Vec3, b: Vec3) -> bool {
+    a.dot(b) > 1.0 - 1e-5
+}
+
+// Keeps the faces for which `keep` returns true, returning the number of removed
+// faces.
+fn retain_faces(model: &mut Model, keep: impl Fn(usize) -> bool) -> usize {
+    let count = model.faces.len() / 3;
+    let mut faces = Vec::with_capacity(model.faces.len());
+    let mut face_uvs = Vec::with_capacity(model.face_uvs.len());
+    let mut face_materials = Vec::with_capacity(model.face_materials.len());
+    for face in 0..count {
+        if !keep(face) {
+            continue;
+        }
+        faces.extend_from_slice(&model.faces[face * 3..face * 3 + 3]);
+        if !model.face_uvs.is_empty() {
+            face_uvs.extend_from_slice(&model.face_uvs[face * 3..face * 3 + 3]);
+        }
+        if !model.face_materials.is_empty() {
+            face_materials.push(model.face_materials[face]);
+        }
+    }
+
+    model.faces = faces;
+    model.face_uvs = face_uvs;
+    model.face_materials = face_materials;
+    count - model.faces.len() / 3
+}
+
+// Rebuilds every vertex attribute so that vertex `i` is a copy of the old vertex
+// `sources[i]`. Faces are left for the caller to remap.
+fn select_vertices(model: &mut Model, sources: &[usize]) {
+    fn select<T: Copy>(values: &mut Vec<T>, sources: &[usize]) {
+        if !values.is_empty() {
+            *values = sources.iter().map(|i| values[*i]).collect();
+        }
+    }
+
+    select(&mut model.verts, sources);
+    select(&mut model.normals, sources);
+    select(&mut model.tangents, sources);
+    select(&mut model.joints, sources);
+    select(&mut model.weights, sources);
+    for target in model.morph_targets.iter_mut() {
+        select(&mut target.position_deltas, sources);
+        select(&mut target.normal_deltas, sources);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit quad in the xy plane facing +z, split into two triangles that don't
+    // share vertices. `u` scales the uvs along x, so -1.0 mirrors them.
+    fn split_quad(u: f32) -> Model {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let mut model = Model::default();
+        for corner in [0, 1, 2, 0, 2, 3] {
+            let (x, y) = corners[corner];
+            model.verts.push(Vec3::new(x, y, 0.0));
+            model.normals.push(Vec3::z(1.0));
+            model.uvs.push(Vec2::new(x * u, y));
+        }
+        model.faces = (0..6).collect();
+        model.face_uvs = (0..6).collect();
+        model
+    }
+
+    #[test]
+    fn weld_merges_shared_corners() {
+        let mut model = split_quad(1.0);
+        model.verts[3] += Vec3::x(1e-5);
+        assert_eq!(weld_vertices(&mut model, 1e-4), 2);
+        assert_eq!(model.verts.len(), 4);
+        assert_eq!(model.normals.len(), 4);
+        assert_eq!(model.faces, [0, 1, 2, 0, 2, 3]);
+        assert!(validate(&model).is_clean(), "{}", validate(&model));
+
+        // Nothing else is close enough.
+        assert_eq!(weld_vertices(&mut model, 1e-4), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid weld epsilon")]
+    fn weld_rejects_zero_epsilon() {
+        weld_vertices(&mut split_quad(1.0), 0.0);
+    }
+
+    #[test]
+    fn degenerate_faces_are_removed() {
+        let mut model = split_quad(1.0);
+        // A face that reuses a vertex, and one with its corners on a line.
+        model.verts.push(Vec3::x(2.0));
+        model.normals.push(Vec3::z(1.0));
+        model.faces.extend_from_slice(&[0, 0, 1, 0, 1, 6]);
+        model.face_uvs.extend_from_slice(&[0, 0, 1, 0, 1, 1]);
+        assert_eq!(validate(&model).degenerate_faces, 2);
+
+        assert_eq!(remove_degenerate_faces(&mut model, 1e-6), 2);
+        assert_eq!(model.faces, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(model.face_uvs, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(remove_degenerate_faces(&mut model, 1e-6), 0);
+    }
+
+    // Two faces folded 90 degrees along the x axis, facing +z and +y.
+    fn fold() -> Model {
+        Model {
+            verts: vec![Vec3::ZERO, Vec3::x(1.0), Vec3::y(1.0), Vec3::z(1.0)],
+            faces: vec![0, 1, 2, 0, 3, 1],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn smooth_normals_split_hard_edges() {
+        let mut model = fold();
+        compute_smooth_normals(&mut model, 30f32.to_radians());
+        // Both vertices on the fold get a copy for the second face.
+        assert_eq!(model.verts.len(), 6);
+        assert_eq!(model.normals.len(), 6);
+        for (face, normal) in model.faces.chunks(3).zip([Vec3::z(1.0), Vec3::y(1.0)]) {
+            for v in face {
+                assert!(model.normals[*v].distance(normal) < 1e-5, "{face:?}");
+            }
+        }
+        assert!(validate(&model).is_clean(), "{}", validate(&model));
+    }
+
+    #[test]
+    fn smooth_normals_blend_soft_edges() {
+        let mut model = fold();
+        compute_smooth_normals(&mut model, 100f32.to_radians());
+        assert_eq!(model.verts.len(), 4);
+        let blended = Vec3::new(0.0, 1.0, 1.0).normalize();
+        for (v, expected) in [
+            (0, blended),
+            (1, blended),
+            (2, Vec3::z(1.0)),
+            (3, Vec3::y(1.0)),
+        ] {
+            assert!(model.normals[v].distance(expected) < 1e-5, "{v}");
+        }
+    }
+
+    #[test]
+    fn duplicate_faces_keep_their_back_side() {
+        let mut model = split_quad(1.0);
+        weld_vertices(&mut model, 1e-4);
+        // The first face again rotated, and its back side.
+        model.faces.extend_from_slice(&[1, 2, 0, 0, 2, 1]);
+        model.face_uvs.extend_from_slice(&[1, 2, 0, 0, 2, 1]);
+        assert_eq!(validate(&model).duplicate_faces, 1);
+
+        assert_eq!(remove_duplicate_faces(&mut model), 1);
+        assert_eq!(model.faces, [0, 1, 2, 0, 2, 3, 0, 2, 1]);
+        assert_eq!(model.face_uvs.len(), model.faces.len());
+        assert_eq!(remove_duplicate_faces(&mut model), 0);
+    }
+
+    #[test]
+    fn tangents_follow_the_uvs() {
+        for (u, sign) in [(1.0, 1.0), (-1.0, -1.0)] {
+            let mut model = split_quad(u);
+            compute_tangents(&mut model);
+            assert_eq!(model.tangents.len(), model.verts.len());
+            for tangent in model.tangents.iter() {
+                let expected = Vec3::x(u);
+                assert!(tangent.reduce().distance(expected) < 1e-5, "{tangent:?}");
+                assert_eq!(tangent.w, sign);
+            }
+        }
+    }
+
+    #[test]
+    fn validate_reports_problems() {
+        let mut model = split_quad(1.0);
+        assert!(validate(&model).is_clean(), "{}", validate(&model));
+
+        model.verts.push(Vec3::ZERO);
+        model.faces.extend_from_slice(&[0, 1, 0]);
+        let report = validate(&model);
+        assert_eq!(report.mismatched_attributes, ["face_uvs", "normals"]);
+        assert_eq!(report.degenerate_faces, 1);
+        assert_eq!(report.unused_verts, 1);
+        assert!(!report.is_valid());
+
+        model.faces.push(7);
+        let report = validate(&model);
+        assert_eq!(report.vertex_indices_out_of_range, 1);
+        assert!(report.mismatched_attributes.contains(&"faces"));
+    }
+}
@@ -1,4 +1,10 @@
-use crate::{camera::Camera, math::*};
+use crate::{
+    background::{self, Background},
+    camera::Camera,
+    fog::DepthConvention,
+    math::*,
+    sprite::{self, Sprite, SpriteSize},
+};
 use rast::tint::*;
 use std::collections::VecDeque;
 
@@ -9,6 +15,7 @@ const NEPTUNE_DIST: f64 = 4.4706e12;
 
 pub struct NeutronMemory {
     bodies: [CelestialBody; 9],
+    background: Background,
 }
 
 impl Default for NeutronMemory {
@@ -37,6 +44,11 @@ impl Default for NeutronMemory {
                 // Neptune
                 CelestialBody::new(fetch_spatial_data(899), Srgb::from_rgb(255, 255, 255)),
             ],
+            background: Background::Starfield {
+                color: Srgb::from_rgb(255, 255, 255),
+                density: 0.02,
+                seed: 0,
+            },
         }
     }
 }
@@ -131,9 +143,16 @@ pub fn render(
     height: usize,
     camera: &Camera,
 ) {
-    frame_buffer.fill(Srgb::from_rgb(0, 0, 0));
     zbuffer.fill(1.0);
-    draw_vanishing_lines(frame_buffer, width, height, camera);
+    background::draw_background(
+        &memory.background,
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+    );
+    draw_vanishing_lines(frame_buffer, zbuffer, width, height, camera);
     draw_system(memory, frame_buffer, zbuffer, width, height, camera);
 }
 
@@ -151,83 +170,62 @@ fn draw_system(
         }
 
         for i in 0..body.trail.len() - 1 {
-            let p1 = body.trail[i];
-            let p2 = body.trail[i + 1];
-
-            let v1 = crate::math::vertex_world_to_screen_space_clipped(width, height, camera, p1);
-            let v2 = crate::math::vertex_world_to_screen_space_clipped(width, height, camera, p2);
-            if let (Some(v1), Some(v2)) = (v1, v2) {
-                rast::rast_line_checked(
-                    frame_buffer,
-                    zbuffer,
-                    width,
-                    height,
-                    v1.x as i32,
-                    v1.y as i32,
-                    v1.z,
-                    v2.x as i32,
-                    v2.y as i32,
-                    v2.z,
-                    body.color,
-                );
-            }
-        }
-    }
-
-    for body in memory.bodies.iter() {
-        let t = (body.spatial.translation / AU * SCALE).to_vec3();
-        if let Some(proj) =
-            crate::math::vertex_world_to_screen_space_clipped(width, height, camera, t)
-        {
-            let scale = 20.0;
-            let corners = [
-                Vec2::new(0.0, 0.0),
-                Vec2::new(1.0, 0.0),
-                Vec2::new(1.0, 1.0),
-                Vec2::new(0.0, 1.0),
-            ]
-            .map(|v| (v - 0.5) * scale + proj.reduce());
-
-            rast::rast_quad_colored(
+            crate::raster::rast_line_world(
                 frame_buffer,
+                zbuffer,
                 width,
                 height,
-                corners[0].x as i32,
-                corners[0].y as i32,
-                corners[1].x as i32,
-                corners[1].y as i32,
-                corners[2].x as i32,
-                corners[2].y as i32,
-                corners[3].x as i32,
-                corners[3].y as i32,
+                camera,
+                body.trail[i],
+                body.trail[i + 1],
                 body.color,
             );
         }
     }
+
+    let mut bodies = memory
+        .bodies
+        .iter()
+        .map(|body| {
+            Sprite::colored(
+                (body.spatial.translation / AU * SCALE).to_vec3(),
+                SpriteSize::Pixels(Vec2::splat(20.0)),
+                body.color,
+            )
+        })
+        .collect::<Vec<_>>();
+    sprite::draw_sprites(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        DepthConvention::Linear,
+        &mut bodies,
+    );
 }
 
-// TODO: Need a way to clip the points such that when one is offscreen
-// the projection is correct.
-#[allow(unused)]
-fn draw_vanishing_lines(frame_buffer: &mut [Srgb], width: usize, height: usize, camera: &Camera) {
+fn draw_vanishing_lines(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+) {
     for [p1, p2] in [
         [Vec3::x(-100.0), Vec3::x(100.0)],
         [Vec3::z(-100.0), Vec3::z(100.0)],
     ] {
-        // let v1 = crate::math::vertex_world_to_screen_space(width, height, camera, p1);
-        // let v2 = crate::math::vertex_world_to_screen_space(width, height, camera, p2);
-        // rast::rast_line(
-        //     frame_buffer,
-        //     width,
-        //     height,
-        //     v1.x as i32,
-        //     v1.y as i32,
-        //     v1.z,
-        //     v2.x as i32,
-        //     v2.y as i32,
-        //     v2.z,
-        //     Srgb::rgb(62, 62, 62),
-        // );
+        crate::raster::rast_line_world(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            camera,
+            p1,
+            p2,
+            Srgb::from_rgb(62, 62, 62),
+        );
     }
 }
 
@@ -0,0 +1,398 @@
+use rast::tint::*;
+
+// Post processing runs on the final `Srgb` frame after every demo has drawn, in
+// the order the stages were pushed.
+
+pub enum Effect {
+    Fxaa,
+    // Darkens the frame towards the corners, starting at `radius` from the center
+    // where 1 is the distance to a corner.
+    Vignette { strength: f32, radius: f32 },
+    // Quantizes each channel to `levels` levels, offset by a threshold pattern to
+    // hide the banding.
+    Dither { pattern: DitherPattern, levels: u8 },
+    ColorGrade(Lut3d),
+    Sharpen { amount: f32 },
+}
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fxaa => "fxaa",
+            Self::Vignette { .. } => "vignette",
+            Self::Dither { .. } => "dither",
+            Self::ColorGrade(_) => "color grade",
+            Self::Sharpen { .. } => "sharpen",
+        }
+    }
+}
+
+pub enum DitherPattern {
+    Bayer4,
+    // Rank of each texel in a tileable blue noise texture, see `blue_noise`.
+    BlueNoise { size: usize, ranks: Vec<u16> },
+}
+
+pub struct Stage {
+    pub effect: Effect,
+    pub enabled: bool,
+    // Time taken by the last run.
+    pub millis: f64,
+}
+
+#[derive(Default)]
+pub struct PostChain {
+    pub stages: Vec<Stage>,
+    // Copy of the frame for effects that read neighbouring pixels.
+    scratch: Vec<Srgb>,
+}
+
+impl PostChain {
+    pub fn push(&mut self, effect: Effect, enabled: bool) {
+        self.stages.push(Stage {
+            effect,
+            enabled,
+            millis: 0.0,
+        });
+    }
+
+    pub fn run(&mut self, frame_buffer: &mut [Srgb], zbuffer: &[f32], width: usize, height: usize) {
+        debug_assert_eq!(frame_buffer.len(), zbuffer.len());
+        debug_assert_eq!(frame_buffer.len(), width * height);
+
+        for stage in self.stages.iter_mut() {
+            if !stage.enabled {
+                stage.millis = 0.0;
+                continue;
+            }
+
+            let scratch = &mut self.scratch;
+            let (millis, _) = glazer::debug_time_millis(|| match &stage.effect {
+                Effect::Fxaa => {
+                    copy_to_scratch(scratch, frame_buffer);
+                    fxaa(frame_buffer, scratch, width, height);
+                }
+                Effect::Vignette { strength, radius } => {
+                    vignette(frame_buffer, width, height, *strength, *radius);
+                }
+                Effect::Dither { pattern, levels } => {
+                    dither(frame_buffer, width, height, pattern, *levels);
+                }
+                Effect::ColorGrade(lut) => {
+                    for pixel in frame_buffer.iter_mut() {
+                        *pixel = lut.apply(*pixel);
+                    }
+                }
+                Effect::Sharpen { amount } => {
+                    copy_to_scratch(scratch, frame_buffer);
+                    sharpen(frame_buffer, scratch, width, height, *amount);
+                }
+            });
+            stage.millis = millis;
+        }
+    }
+}
+
+fn copy_to_scratch(scratch: &mut Vec<Srgb>, frame_buffer: &[Srgb]) {
+    scratch.clear();
+    scratch.extend_from_slice(frame_buffer);
+}
+
+fn to_rgb(color: Srgb) -> [f32; 3] {
+    [color.r as f32, color.g as f32, color.b as f32]
+}
+
+fn from_rgb([r, g, b]: [f32; 3], a: u8) -> Srgb {
+    let c = |x: f32| x.clamp(0.0, 255.0) as u8;
+    Srgb::new(c(r), c(g), c(b), a)
+}
+
+fn luma(color: Srgb) -> f32 {
+    let [r, g, b] = to_rgb(color);
+    (0.299 * r + 0.587 * g + 0.114 * b) / 255.0
+}
+
+fn texel(buffer: &[Srgb], width: usize, height: usize, x: isize, y: isize) -> Srgb {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    buffer[y * width + x]
+}
+
+// Bilinear sample at pixel coordinates, where pixel centers are at integers.
+fn sample(buffer: &[Srgb], width: usize, height: usize, x: f32, y: f32) -> [f32; 3] {
+    let x0 = libm::floorf(x);
+    let y0 = libm::floorf(y);
+    let tx = x - x0;
+    let ty = y - y0;
+    let (x0, y0) = (x0 as isize, y0 as isize);
+
+    let top = lerp_rgb(
+        to_rgb(texel(buffer, width, height, x0, y0)),
+        to_rgb(texel(buffer, width, height, x0 + 1, y0)),
+        tx,
+    );
+    let bottom = lerp_rgb(
+        to_rgb(texel(buffer, width, height, x0, y0 + 1)),
+        to_rgb(texel(buffer, width, height, x0 + 1, y0 + 1)),
+        tx,
+    );
+    lerp_rgb(top, bottom, ty)
+}
+
+fn lerp_rgb(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    core::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+// FXAA 3.11 console variant. Pixels are blurred along the edge direction found
+// from the luma of their diagonal neighbours.
+fn fxaa(frame_buffer: &mut [Srgb], source: &[Srgb], width: usize, height: usize) {
+    const EDGE_THRESHOLD: f32 = 0.125;
+    const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+    const REDUCE_MUL: f32 = 1.0 / 8.0;
+    const REDUCE_MIN: f32 = 1.0 / 128.0;
+    const SPAN_MAX: f32 = 8.0;
+
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            let l = |dx: isize, dy: isize| luma(texel(source, width, height, xi + dx, yi + dy));
+            let (nw, ne, sw, se, m) = (l(-1, -1), l(1, -1), l(-1, 1), l(1, 1), l(0, 0));
+
+            let min = m.min(nw.min(ne).min(sw.min(se)));
+            let max = m.max(nw.max(ne).max(sw.max(se)));
+            if max - min < EDGE_THRESHOLD_MIN.max(max * EDGE_THRESHOLD) {
+                continue;
+            }
+
+            let dir_x = -((nw + ne) - (sw + se));
+            let dir_y = (nw + sw) - (ne + se);
+            let reduce = ((nw + ne + sw + se) * 0.25 * REDUCE_MUL).max(REDUCE_MIN);
+            let scale = 1.0 / (libm::fabsf(dir_x).min(libm::fabsf(dir_y)) + reduce);
+            let dir_x = (dir_x * scale).clamp(-SPAN_MAX, SPAN_MAX);
+            let dir_y = (dir_y * scale).clamp(-SPAN_MAX, SPAN_MAX);
+
+            let tap = |t: f32| {
+                sample(
+                    source,
+                    width,
+                    height,
+                    x as f32 + dir_x * t,
+                    y as f32 + dir_y * t,
+                )
+            };
+            let rgb_a = lerp_rgb(tap(1.0 / 3.0 - 0.5), tap(2.0 / 3.0 - 0.5), 0.5);
+            let rgb_b = lerp_rgb(rgb_a, lerp_rgb(tap(-0.5), tap(0.5), 0.5), 0.5);
+
+            let index = y * width + x;
+            let alpha = frame_buffer[index].a;
+            let luma_b = (0.299 * rgb_b[0] + 0.587 * rgb_b[1] + 0.114 * rgb_b[2]) / 255.0;
+            frame_buffer[index] = if luma_b < min || luma_b > max {
+                from_rgb(rgb_a, alpha)
+            } else {
+                from_rgb(rgb_b, alpha)
+            };
+        }
+    }
+}
+
+fn vignette(frame_buffer: &mut [Srgb], width: usize, height: usize, strength: f32, radius: f32) {
+    let cx = width as f32 / 2.0;
+    let cy = height as f32 / 2.0;
+    let corner = libm::sqrtf(cx * cx + cy * cy);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = x as f32 + 0.5 - cx;
+            let dy = y as f32 + 0.5 - cy;
+            let dist = libm::sqrtf(dx * dx + dy * dy) / corner;
+            let t = ((dist - radius) / (1.0 - radius)).clamp(0.0, 1.0);
+            // Smoothstep
+            let t = t * t * (3.0 - 2.0 * t);
+            let index = y * width + x;
+            frame_buffer[index] =
+                crate::raster::scale_srgb(frame_buffer[index], 1.0 - strength * t);
+        }
+    }
+}
+
+fn dither(
+    frame_buffer: &mut [Srgb],
+    width: usize,
+    height: usize,
+    pattern: &DitherPattern,
+    levels: u8,
+) {
+    const BAYER4: [u8; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+
+    let step = 255.0 / (levels.max(2) - 1) as f32;
+    for y in 0..height {
+        for x in 0..width {
+            // Threshold from -0.5 to 0.5 of a step.
+            let threshold = match pattern {
+                DitherPattern::Bayer4 => (BAYER4[(y % 4) * 4 + x % 4] as f32 + 0.5) / 16.0,
+                DitherPattern::BlueNoise { size, ranks } => {
+                    (ranks[(y % size) * size + x % size] as f32 + 0.5) / (size * size) as f32
+                }
+            } - 0.5;
+
+            let index = y * width + x;
+            let [r, g, b] = to_rgb(frame_buffer[index]);
+            let quantize = |c: f32| libm::roundf(c / step + threshold) * step;
+            frame_buffer[index] = from_rgb(
+                [quantize(r), quantize(g), quantize(b)],
+                frame_buffer[index].a,
+            );
+        }
+    }
+}
+
+// Unsharp mask with a cross shaped blur.
+fn sharpen(frame_buffer: &mut [Srgb], source: &[Srgb], width: usize, height: usize, amount: f32) {
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            let t = |dx: isize, dy: isize| to_rgb(texel(source, width, height, xi + dx, yi + dy));
+            let (c, n, s, e, w) = (t(0, 0), t(0, -1), t(0, 1), t(1, 0), t(-1, 0));
+            let rgb = core::array::from_fn(|i| {
+                let blur = (n[i] + s[i] + e[i] + w[i]) * 0.25;
+                c[i] + (c[i] - blur) * amount
+            });
+            let index = y * width + x;
+            frame_buffer[index] = from_rgb(rgb, frame_buffer[index].a);
+        }
+    }
+}
+
+/// Generates a tileable `size` by `size` blue noise texture with the void and
+/// cluster method, returning the rank of each texel. Slow, so it should be
+/// generated once up front. `size` is at most 256 so that the ranks fit in a `u16`.
+pub fn blue_noise(size: usize) -> Vec<u16> {
+    const SIGMA: f32 = 1.5;
+    assert!(
+        size > 0 && size <= 256,
+        "blue noise size must be 1 to 256: {size}"
+    );
+
+    let count = size * size;
+    let mut ones = vec![false; count];
+    let mut energy = vec![0.0f32; count];
+    let splat = |energy: &mut [f32], index: usize, sign: f32| {
+        let (px, py) = ((index % size) as isize, (index / size) as isize);
+        for y in 0..size as isize {
+            for x in 0..size as isize {
+                // Wraps around so that the texture tiles.
+                let dx = (x - px).rem_euclid(size as isize);
+                let dy = (y - py).rem_euclid(size as isize);
+                let dx = dx.min(size as isize - dx) as f32;
+                let dy = dy.min(size as isize - dy) as f32;
+                energy[y as usize * size + x as usize] +=
+                    sign * libm::expf(-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA));
+            }
+        }
+    };
+    let tightest_cluster = |ones: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|i| ones[*i])
+            .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+            .unwrap()
+    };
+    let largest_void = |ones: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|i| !ones[*i])
+            .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+            .unwrap()
+    };
+
+    // Random initial pattern, relaxed until moving the tightest cluster into the
+    // largest void does nothing. Capped in case the pattern oscillates.
+    let initial = (count / 10).max(1);
+    let mut placed = 0;
+    let mut rng = crate::rng::Rng::new(size as u32, 0);
+    while placed < initial {
+        let index = rng.next_u32() as usize % count;
+        if !ones[index] {
+            ones[index] = true;
+            splat(&mut energy, index, 1.0);
+            placed += 1;
+        }
+    }
+    for _ in 0..count {
+        let cluster = tightest_cluster(&ones, &energy);
+        ones[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&ones, &energy);
+        ones[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0u16; count];
+    // Ranks below the initial pattern, removing the tightest clusters first.
+    let (mut phase_ones, mut phase_energy) = (ones.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&phase_ones, &phase_energy);
+        phase_ones[cluster] = false;
+        splat(&mut phase_energy, cluster, -1.0);
+        ranks[cluster] = rank as u16;
+    }
+    // Ranks above it, filling the largest voids first.
+    for rank in initial..count {
+        let void = largest_void(&ones, &energy);
+        ones[void] = true;
+        splat(&mut energy, void, 1.0);
+        ranks[void] = rank as u16;
+    }
+
+    ranks
+}
+
+// Color lookup table indexed by red, then green, then blue, like `.cube` files.
+pub struct Lut3d {
+    pub size: usize,
+    pub table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Builds a table from `f`, which maps colors from 0 to 1. `size` must be at
+    /// least 2, one entry for each end of the range.
+    pub fn from_fn(size: usize, f: impl Fn([f32; 3]) -> [f32; 3]) -> Self {
+        assert!(size >= 2, "lut size must be at least 2: {size}");
+        let scale = 1.0 / (size - 1) as f32;
+        let mut table = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push(f([r as f32 * scale, g as f32 * scale, b as f32 * scale]));
+                }
+            }
+        }
+        Self { size, table }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        Self::from_fn(size, |rgb| rgb)
+    }
+
+    // Trilinear lookup.
+    pub fn apply(&self, color: Srgb) -> Srgb {
+        let max = (self.size - 1) as f32;
+        let [r, g, b] = to_rgb(color).map(|c| c / 255.0 * max);
+        let (r0, g0, b0) = (libm::floorf(r), libm::floorf(g), libm::floorf(b));
+        let (tr, tg, tb) = (r - r0, g - g0, b - b0);
+        let (r0, g0, b0) = (r0 as usize, g0 as usize, b0 as usize);
+        let (r1, g1, b1) = (
+            (r0 + 1).min(self.size - 1),
+            (g0 + 1).min(self.size - 1),
+            (b0 + 1).min(self.size - 1),
+        );
+
+        let at = |r: usize, g: usize, b: usize| self.table[(b * self.size + g) * self.size + r];
+        let c00 = lerp_rgb(at(r0, g0, b0), at(r1, g0, b0), tr);
+        let c10 = lerp_rgb(at(r0, g1, b0), at(r1, g1, b0), tr);
+        let c01 = lerp_rgb(at(r0, g0, b1), at(r1, g0, b1), tr);
+        let c11 = lerp_rgb(at(r0, g1, b1), at(r1, g1, b1), tr);
+        let rgb = lerp_rgb(lerp_rgb(c00, c10, tg), lerp_rgb(c01, c11, tg), tb);
+        from_rgb(rgb.map(|c| c * 255.0), color.a)
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{camera::Camera, math::*};
+use rast::tint::*;
+
+// Fragment level rasterization for the passes that need more control than `rast`
+// gives, e.g. testing depth without writing it or discarding fragments.
+
+/// Calls `fragment` for every pixel center covered by the screen space triangle
+/// with the pixel coordinates, the interpolated depth and the barycentric weights
+/// of `v1`, `v2` and `v3`. Pixel centers on an edge follow the top-left rule, so
+/// triangles that share an edge never both cover the same pixel.
+pub fn rast_triangle_fragments(
+    width: usize,
+    height: usize,
+    v1: Vec3,
+    v2: Vec3,
+    v3: Vec3,
+    mut fragment: impl FnMut(usize, usize, f32, Vec3),
+) {
+    fn edge(a: Vec3, b: Vec3, px: f32, py: f32) -> f32 {
+        (b.x - a.x) * (py - a.y) - (b.y - a.y) * (px - a.x)
+    }
+
+    // Whether pixel centers exactly on the edge from `a` to `b` belong to this
+    // triangle: only if the triangle is to the right of a left edge, or below a
+    // horizontal top edge. The triangle on the other side of the edge sees it
+    // facing the other way, so exactly one of them draws those pixels.
+    //
+    // https://learn.microsoft.com/en-us/windows/win32/direct3d11/d3d10-graphics-programming-guide-rasterizer-stage-rules#triangle-rasterization-rules-without-multisampling
+    fn is_top_left(a: Vec3, b: Vec3, area: f32) -> bool {
+        // Points into the triangle.
+        let nx = (a.y - b.y) * area.signum();
+        let ny = (b.x - a.x) * area.signum();
+        nx > 0.0 || (nx == 0.0 && ny > 0.0)
+    }
+
+    let area = edge(v1, v2, v3.x, v3.y);
+    if area == 0.0 || width == 0 || height == 0 {
+        return;
+    }
+    let top_left = [
+        is_top_left(v2, v3, area),
+        is_top_left(v3, v1, area),
+        is_top_left(v1, v2, area),
+    ];
+    let covers = |w: f32, top_left: bool| w > 0.0 || (w == 0.0 && top_left);
+
+    let minx = v1.x.min(v2.x).min(v3.x).max(0.0);
+    let miny = v1.y.min(v2.y).min(v3.y).max(0.0);
+    let maxx = v1.x.max(v2.x).max(v3.x).min(width as f32 - 1.0);
+    let maxy = v1.y.max(v2.y).max(v3.y).min(height as f32 - 1.0);
+    if minx > maxx || miny > maxy {
+        return;
+    }
+
+    for y in libm::floorf(miny) as usize..=libm::ceilf(maxy) as usize {
+        let py = y as f32 + 0.5;
+        for x in libm::floorf(minx) as usize..=libm::ceilf(maxx) as usize {
+            let px = x as f32 + 0.5;
+            let w1 = edge(v2, v3, px, py) / area;
+            let w2 = edge(v3, v1, px, py) / area;
+            let w3 = edge(v1, v2, px, py) / area;
+            if !covers(w1, top_left[0]) || !covers(w2, top_left[1]) || !covers(w3, top_left[2]) {
+                continue;
+            }
+
+            let z = v1.z * w1 + v2.z * w2 + v3.z * w3;
+            fragment(x, y, z, Vec3::new(w1, w2, w3));
+        }
+    }
+}
+
+pub fn sample_texture(texture: &(usize, usize, Vec<Srgb>), uv: Vec2, bilinear: bool) -> Srgb {
+    let (width, height, pixels) = texture;
+    // Texture rows are stored top to bottom, uvs go bottom to top.
+    let u = (uv.x - libm::floorf(uv.x)) * *width as f32;
+    let v = (1.0 - (uv.y - libm::floorf(uv.y))) * *height as f32;
+    let texel = |x: isize, y: isize| {
+        let x = x.rem_euclid(*width as isize) as usize;
+        let y = y.rem_euclid(*height as isize) as usize;
+        pixels[y * width + x]
+    };
+
+    if !bilinear {
+        return texel(libm::floorf(u) as isize, libm::floorf(v) as isize);
+    }
+
+    let u = u - 0.5;
+    let v = v - 0.5;
+    let x = libm::floorf(u);
+    let y = libm::floorf(v);
+    let tx = u - x;
+    let ty = v - y;
+    let x = x as isize;
+    let y = y as isize;
+
+    let top = lerp_srgb(texel(x, y), texel(x + 1, y), tx);
+    let bottom = lerp_srgb(texel(x, y + 1), texel(x + 1, y + 1), tx);
+    lerp_srgb(top, bottom, ty)
+}
+
+/// Draws the world space segment from `v1` to `v2` with the depth of the Euler
+/// angle transforms in `math`, clipped to the view frustum before projection.
+pub fn rast_line_world(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    v1: Vec3,
+    v2: Vec3,
+    color: Srgb,
+) {
+    if let Some((v1, v2)) = line_world_to_screen_space_clipped(width, height, camera, v1, v2) {
+        rast::rast_line_checked(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            libm::floorf(v1.x) as i32,
+            libm::floorf(v1.y) as i32,
+            v1.z,
+            libm::floorf(v2.x) as i32,
+            libm::floorf(v2.y) as i32,
+            v2.z,
+            color,
+        );
+    }
+}
+
+/// Draws the world space segment from `v1` to `v2` with the perspective depth of
+/// the matrix draw functions in `model`, clipped in clip space before the divide.
+pub fn rast_line_matrix(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    view_proj_matrix: &Mat4,
+    v1: Vec3,
+    v2: Vec3,
+    color: Srgb,
+) {
+    let mut v1 = view_proj_matrix.mult_vec4(v1.extend(1.0));
+    let mut v2 = view_proj_matrix.mult_vec4(v2.extend(1.0));
+
+    // Distances to the clip planes, where points inside are positive.
+    let planes: [fn(Vec4) -> f32; 6] = [
+        |v| v.w + v.x,
+        |v| v.w - v.x,
+        |v| v.w + v.y,
+        |v| v.w - v.y,
+        |v| v.z,
+        |v| v.w - v.z,
+    ];
+    for plane in planes {
+        let (d1, d2) = (plane(v1), plane(v2));
+        if d1 < 0.0 && d2 < 0.0 {
+            return;
+        }
+        if d1 < 0.0 {
+            v1 = v1 + (v2 - v1) * (d1 / (d1 - d2));
+        } else if d2 < 0.0 {
+            v2 = v2 + (v1 - v2) * (d2 / (d2 - d1));
+        }
+    }
+
+    let screen = |v: Vec4| {
+        Vec3::new(
+            (v.x / v.w + 1.0) / 2.0 * width as f32,
+            (1.0 - v.y / v.w) / 2.0 * height as f32,
+            v.z / v.w,
+        )
+    };
+    let (v1, v2) = (screen(v1), screen(v2));
+    rast::rast_line_checked(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        libm::floorf(v1.x) as i32,
+        libm::floorf(v1.y) as i32,
+        v1.z,
+        libm::floorf(v2.x) as i32,
+        libm::floorf(v2.y) as i32,
+        v2.z,
+        color,
+    );
+}
+
+pub fn lerp_srgb(a: Srgb, b: Srgb, t: f32) -> Srgb {
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t) as u8;
+    Srgb::new(
+        lerp(a.r, b.r),
+        lerp(a.g, b.g),
+        lerp(a.b, b.b),
+        lerp(a.a, b.a),
+    )
+}
+
+/// Source over blend of `src` onto `dst`, weighted by `alpha`.
+pub fn blend_srgb(dst: Srgb, src: Srgb, alpha: f32) -> Srgb {
+    let alpha = alpha.clamp(0.0, 1.0);
+    let mix = |d: u8, s: u8| (s as f32 * alpha + d as f32 * (1.0 - alpha)) as u8;
+    Srgb::new(
+        mix(dst.r, src.r),
+        mix(dst.g, src.g),
+        mix(dst.b, src.b),
+        dst.a,
+    )
+}
+
+pub fn scale_srgb(color: Srgb, factor: f32) -> Srgb {
+    let scale = |c: u8| (c as f32 * factor).clamp(0.0, 255.0) as u8;
+    Srgb::new(scale(color.r), scale(color.g), scale(color.b), color.a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts how often each pixel is covered by the triangles.
+    fn coverage(width: usize, height: usize, triangles: &[[Vec3; 3]]) -> Vec<u32> {
+        let mut counts = vec![0; width * height];
+        for [v1, v2, v3] in triangles {
+            rast_triangle_fragments(width, height, *v1, *v2, *v3, |x, y, _, _| {
+                counts[y * width + x] += 1;
+            });
+        }
+        counts
+    }
+
+    #[test]
+    fn shared_edges_are_covered_once() {
+        // Corners on pixel centers put every pixel of the diagonal and the
+        // shared vertical edge exactly on an edge.
+        let v = |x: f32, y: f32| Vec3::new(x + 0.5, y + 0.5, 0.0);
+        let (a, b, c, d, e, f) = (
+            v(0.0, 0.0),
+            v(6.0, 0.0),
+            v(6.0, 6.0),
+            v(0.0, 6.0),
+            v(12.0, 0.0),
+            v(12.0, 6.0),
+        );
+        // Mixed windings, as with backface culling off.
+        let counts = coverage(16, 8, &[[a, b, c], [a, d, c], [b, e, f], [b, c, f]]);
+        assert!(counts.iter().all(|count| *count <= 1), "{counts:?}");
+    }
+
+    #[test]
+    fn quads_have_no_gaps() {
+        let (a, b, c, d) = (
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(8.0, 0.0, 0.0),
+            Vec3::new(8.0, 8.0, 0.0),
+            Vec3::new(0.0, 8.0, 0.0),
+        );
+        let counts = coverage(8, 8, &[[a, b, c], [a, c, d]]);
+        assert!(counts.iter().all(|count| *count == 1), "{counts:?}");
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{raster, target::RenderTarget};
+use rast::tint::*;
+
+// Renders the scene into an internal target at a fraction of the window
+// resolution, and upscales it into the frame.
+pub struct RenderScale {
+    // Fraction of the window resolution on each axis, from `MIN_SCALE` to 1.
+    pub scale: f32,
+    pub filter: UpscaleFilter,
+    // Adjusts `scale` every frame when set.
+    pub auto: Option<AutoScale>,
+    pub target: RenderTarget,
+}
+
+pub const MIN_SCALE: f32 = 0.25;
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            filter: UpscaleFilter::Bilinear,
+            auto: None,
+            target: RenderTarget::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UpscaleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+// Holds a target frame time by scaling the resolution. The cost of a frame is
+// assumed to be proportional to the number of pixels drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoScale {
+    // Target frame time in seconds.
+    pub target: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    average: f32,
+    cooldown: f32,
+}
+
+impl AutoScale {
+    pub fn new(target: f32, min_scale: f32, max_scale: f32) -> Self {
+        Self {
+            target,
+            min_scale,
+            max_scale,
+            average: target,
+            cooldown: 0.0,
+        }
+    }
+}
+
+impl RenderScale {
+    /// Whether the scene is drawn straight into the frame.
+    pub fn is_native(&self) -> bool {
+        self.scale >= 1.0
+    }
+
+    /// Feeds the last frame time to the automatic mode.
+    pub fn update(&mut self, delta: f32) {
+        // Resizing too often makes the frame time jump around, so the scale only
+        // moves after the average has settled.
+        const SMOOTHING: f32 = 0.1;
+        const COOLDOWN: f32 = 0.5;
+        const MAX_STEP: f32 = 0.1;
+        const ROUNDING: f32 = 0.05;
+
+        let Some(auto) = self.auto.as_mut() else {
+            return;
+        };
+        auto.average += (delta - auto.average) * SMOOTHING;
+        auto.cooldown -= delta;
+        if auto.cooldown > 0.0 {
+            return;
+        }
+
+        let ideal = self.scale * libm::sqrtf(auto.target / auto.average);
+        let step = (ideal - self.scale).clamp(-MAX_STEP, MAX_STEP);
+        let scale = libm::roundf((self.scale + step) / ROUNDING) * ROUNDING;
+        let scale = scale
+            .clamp(auto.min_scale, auto.max_scale)
+            .clamp(MIN_SCALE, 1.0);
+        if scale != self.scale {
+            self.scale = scale;
+            auto.cooldown = COOLDOWN;
+        }
+    }
+
+    /// Resizes the internal target for a `width` by `height` frame.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.scale = self.scale.clamp(MIN_SCALE, 1.0);
+        self.target.resize(
+            ((width as f32 * self.scale) as usize).max(1),
+            ((height as f32 * self.scale) as usize).max(1),
+        );
+    }
+
+    /// Upscales the internal target into `frame_buffer` with `filter`. Depth is
+    /// always taken from the nearest internal pixel, since blending depth across
+    /// edges would place pixels between surfaces. Must be called after `resize`.
+    pub fn upscale(
+        &self,
+        frame_buffer: &mut [Srgb],
+        zbuffer: &mut [f32],
+        width: usize,
+        height: usize,
+    ) {
+        debug_assert_eq!(frame_buffer.len(), width * height);
+
+        let RenderTarget {
+            width: internal_width,
+            height: internal_height,
+            ref color,
+            ref depth,
+        } = self.target;
+        let step_x = internal_width as f32 / width as f32;
+        let step_y = internal_height as f32 / height as f32;
+        let texel = |x: usize, y: usize| color[y * internal_width + x];
+
+        for y in 0..height {
+            // Pixel centers in internal pixels.
+            let sy = ((y as f32 + 0.5) * step_y - 0.5).max(0.0);
+            let y0 = (sy as usize).min(internal_height - 1);
+            let y1 = (y0 + 1).min(internal_height - 1);
+            let ty = sy - y0 as f32;
+            let nearest_y = ((y as f32 + 0.5) * step_y) as usize;
+
+            for x in 0..width {
+                let sx = ((x as f32 + 0.5) * step_x - 0.5).max(0.0);
+                let x0 = (sx as usize).min(internal_width - 1);
+                let x1 = (x0 + 1).min(internal_width - 1);
+                let tx = sx - x0 as f32;
+                let nearest = ((x as f32 + 0.5) * step_x) as usize + nearest_y * internal_width;
+
+                let index = y * width + x;
+                frame_buffer[index] = match self.filter {
+                    UpscaleFilter::Nearest => color[nearest],
+                    UpscaleFilter::Bilinear => {
+                        let top = raster::lerp_srgb(texel(x0, y0), texel(x1, y0), tx);
+                        let bottom = raster::lerp_srgb(texel(x0, y1), texel(x1, y1), tx);
+                        raster::lerp_srgb(top, bottom, ty)
+                    }
+                };
+                zbuffer[index] = depth[nearest];
+            }
+        }
+    }
+}
@@ -6,6 +6,31 @@ pub fn sample_f32(index: usize) -> f32 {
     sample_u32(index) as f32 / 1_000_000_000 as f32
 }
 
+// PCG, seeded from two values, e.g. a pixel and a sample index, so that the
+// sequences are independent.
+//
+// https://www.pcg-random.org/
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32, stream: u32) -> Self {
+        let mut rng = Self(seed.wrapping_mul(0x9e3779b9) ^ stream.wrapping_mul(0x85ebca6b));
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(747796405).wrapping_add(2891336453);
+        let word = ((self.0 >> ((self.0 >> 28) + 4)) ^ self.0).wrapping_mul(277803737);
+        (word >> 22) ^ word
+    }
+
+    // Uniform in 0..1.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
 pub const RNG_TABLE: [u32; 4096] = [
     0x14cec936, 0x06b6c598, 0x225ddbfd, 0x1ab72e92, 0x12e2a1dc, 0x015a89c3, 0x1d432aa5, 0x10093795,
     0x1169af8c, 0x02351fa8, 0x083eff62, 0x10668390, 0x195509cc, 0x343af35a, 0x32f8b96b, 0x385ce052,
@@ -0,0 +1,532 @@
+use crate::{
+    bvh::Bvh,
+    camera::Camera,
+    hiz::{self, DepthPyramid},
+    lod::{self, Lod},
+    math::*,
+    model,
+    shadow::ShadowMap,
+};
+use rast::tint::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+pub struct Node {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    // Index into `Scene::models`.
+    pub model: Option<usize>,
+    // Index into `Scene::materials`, overrides the model's materials.
+    pub material: Option<usize>,
+    // Replaces the model's vertices, e.g. after skinning. Call
+    // `Scene::refit_deformed` after changing them.
+    pub deformed_verts: Option<Vec<Vec3>>,
+    // Hides the node and its children, from the next `Scene::update`.
+    pub visible: bool,
+    // Drawn before every other node when occlusion culling, to hide what is
+    // behind it. Large, simple models make the best occluders.
+    pub occluder: bool,
+
+    parent: Option<NodeId>,
+    world_matrix: Mat4,
+    // Whether the node and all of its ancestors are visible.
+    world_visible: bool,
+    dirty: bool,
+    // Bounds of `deformed_verts`.
+    deformed_obb: Option<model::Obb>,
+    // Hierarchy over `deformed_verts`, and the model it was copied from.
+    deformed_bvh: Option<(usize, Bvh)>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn local_matrix(&self) -> Mat4 {
+        compute_model_matrix(self.translation, self.rotation, self.scale)
+    }
+}
+
+// Nodes are stored in insertion order. A node's parent must already exist when
+// the node is added, so parents always come before their children and the world
+// matrices can be resolved in a single pass.
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    models: Vec<model::Model>,
+    obbs: Vec<model::Obb>,
+    // Built over the undeformed vertices of each model.
+    bvhs: Vec<Bvh>,
+    // Levels of detail for each model, starting with the model itself. Empty if
+    // the model has no other levels.
+    lods: Vec<Vec<Lod>>,
+    pub materials: Vec<model::Material>,
+    // Instance matrices for each model, reused by `render` every frame.
+    instances: Vec<Vec<Mat4>>,
+}
+
+impl Scene {
+    pub fn add_model(&mut self, model: model::Model) -> usize {
+        self.obbs.push(model::compute_obb(&model));
+        self.bvhs.push(Bvh::new(&model.faces, &model.verts));
+        self.models.push(model);
+        self.lods.push(Vec::new());
+        self.models.len() - 1
+    }
+
+    /// Adds `model` along with a simplified level for each `(ratio, screen_size)`
+    /// in `levels`, see [`lod::simplify`]. Each level replaces the previous one
+    /// once the model is smaller than `screen_size` of the screen height.
+    pub fn add_model_with_lods(&mut self, model: model::Model, levels: &[(f32, f32)]) -> usize {
+        let simplified = levels
+            .iter()
+            .map(|(ratio, _)| lod::simplify(&model, *ratio))
+            .collect::<Vec<_>>();
+
+        let base = self.add_model(model);
+        let mut models = vec![base];
+        models.extend(simplified.into_iter().map(|model| self.add_model(model)));
+        self.lods[base] = models
+            .into_iter()
+            .enumerate()
+            .map(|(i, model)| Lod {
+                model,
+                screen_size: levels.get(i).map_or(0.0, |(_, screen_size)| *screen_size),
+            })
+            .collect();
+        base
+    }
+
+    pub fn lods(&self, model: usize) -> &[Lod] {
+        &self.lods[model]
+    }
+
+    /// The level of detail of `model` to draw the node with, or `model` if it has
+    /// no other levels. Deformed nodes are drawn with their own model, so set it
+    /// to this level before deforming its vertices.
+    pub fn lod_model(&self, model: usize, id: NodeId, camera: &Camera) -> usize {
+        let lods = &self.lods[model];
+        if lods.is_empty() {
+            return model;
+        }
+        let world_matrix = self.nodes[id.0].world_matrix;
+        lods[lod::select_lod(lods, camera, self.obbs[model], world_matrix)].model
+    }
+
+    pub fn model(&self, index: usize) -> &model::Model {
+        &self.models[index]
+    }
+
+    pub fn obb(&self, index: usize) -> model::Obb {
+        self.obbs[index]
+    }
+
+    pub fn bvh(&self, index: usize) -> &Bvh {
+        &self.bvhs[index]
+    }
+
+    pub fn add_node(&mut self, parent: Option<NodeId>) -> NodeId {
+        if let Some(parent) = parent {
+            assert!(parent.0 < self.nodes.len(), "parent node does not exist");
+        }
+        self.nodes.push(Node {
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::splat(1.0),
+            model: None,
+            material: None,
+            deformed_verts: None,
+            visible: true,
+            occluder: false,
+            parent,
+            world_matrix: Mat4::IDENTITY,
+            world_visible: true,
+            dirty: true,
+            deformed_obb: None,
+            deformed_bvh: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    /// Marks the node's world matrix, and the world matrices of its descendants,
+    /// out of date.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        let node = &mut self.nodes[id.0];
+        node.dirty = true;
+        node
+    }
+
+    /// Recomputes the bounds of the node's `deformed_verts`, which are used to
+    /// cull it instead of the model's bounds, and refits its hierarchy for
+    /// [`intersect`] and [`occluded`].
+    pub fn refit_deformed(&mut self, id: NodeId) {
+        let node = &mut self.nodes[id.0];
+        let Some(verts) = node.deformed_verts.as_deref() else {
+            node.deformed_obb = None;
+            node.deformed_bvh = None;
+            return;
+        };
+        let model = node.model.expect("deformed node has no model");
+        node.deformed_obb = Some(model::compute_obb_verts(verts));
+
+        // The model's hierarchy is copied again when the node switches models,
+        // e.g. to another level of detail.
+        let mut bvh = match node.deformed_bvh.take() {
+            Some((copied_from, bvh)) if copied_from == model => bvh,
+            _ => self.bvhs[model].clone(),
+        };
+        bvh.refit(&self.models[model].faces, verts);
+        node.deformed_bvh = Some((model, bvh));
+    }
+
+    /// Model space bounds of the node, deformed or not. The node must have a
+    /// model.
+    pub fn node_obb(&self, id: NodeId) -> model::Obb {
+        node_obb(self, &self.nodes[id.0])
+    }
+
+    /// The world matrix computed by the last call to [`Scene::update`].
+    pub fn world_matrix(&self, id: NodeId) -> Mat4 {
+        self.nodes[id.0].world_matrix
+    }
+
+    pub fn update(&mut self) {
+        for i in 0..self.nodes.len() {
+            let parent = self.nodes[i].parent;
+            // Visibility isn't tracked by `dirty`, so it is resolved for every node.
+            let parent_visible = parent.is_none_or(|parent| self.nodes[parent.0].world_visible);
+            self.nodes[i].world_visible = parent_visible && self.nodes[i].visible;
+
+            let parent_dirty = parent.is_some_and(|parent| self.nodes[parent.0].dirty);
+            if !self.nodes[i].dirty && !parent_dirty {
+                continue;
+            }
+
+            let local = self.nodes[i].local_matrix();
+            let world = match parent {
+                Some(parent) => self.nodes[parent.0].world_matrix.mult_mat4(&local),
+                None => local,
+            };
+
+            let node = &mut self.nodes[i];
+            node.world_matrix = world;
+            // Stays dirty until the end of the pass so that children are updated.
+            node.dirty = true;
+        }
+
+        for node in self.nodes.iter_mut() {
+            node.dirty = false;
+        }
+    }
+}
+
+/// Draws every visible node. With `occlusion`, the occluder nodes are drawn first
+/// and the depth pyramid is built from them, then the other nodes are skipped if
+/// they are hidden behind the occluders. The pyramid counts the nodes it culled.
+pub fn render(
+    scene: &mut Scene,
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    shadow_map: Option<&ShadowMap>,
+    occlusion: Option<&mut DepthPyramid>,
+    debug_view: model::DebugView,
+) {
+    let mut instances = core::mem::take(&mut scene.instances);
+    instances.resize_with(scene.models.len(), Vec::new);
+    for instances in instances.iter_mut() {
+        instances.clear();
+    }
+    render_nodes(
+        scene,
+        &mut instances,
+        frame_buffer,
+        zbuffer,
+        camera,
+        width,
+        height,
+        shadow_map,
+        occlusion,
+        debug_view,
+    );
+    scene.instances = instances;
+}
+
+fn render_nodes(
+    scene: &Scene,
+    // Nodes that share an undeformed model without a material override are drawn
+    // together.
+    instances: &mut [Vec<Mat4>],
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    shadow_map: Option<&ShadowMap>,
+    mut occlusion: Option<&mut DepthPyramid>,
+    debug_view: model::DebugView,
+) {
+    // Blended faces from every node are sorted together after the opaque faces.
+    let mut pass = model::DrawPass {
+        shadow_map,
+        debug_view,
+        ..Default::default()
+    };
+
+    let view_proj_matrix = hiz::view_proj_matrix(camera, width, height);
+    if let Some(pyramid) = occlusion.as_deref_mut() {
+        for node in scene.nodes.iter().filter(|node| node.occluder) {
+            if let Some(model) = node.model.filter(|_| node.world_visible) {
+                draw_node(
+                    scene,
+                    node,
+                    model,
+                    frame_buffer,
+                    zbuffer,
+                    camera,
+                    width,
+                    height,
+                    &mut pass,
+                );
+            }
+        }
+        pyramid.build(zbuffer, width, height);
+    }
+
+    for (index, node) in scene.nodes.iter().enumerate() {
+        let Some(model) = node.model else {
+            continue;
+        };
+        if !node.world_visible || (node.occluder && occlusion.is_some()) {
+            continue;
+        }
+
+        // Deformed vertices only match the model they were deformed from.
+        let model = match node.deformed_verts {
+            Some(_) => model,
+            None => scene.lod_model(model, NodeId(index), camera),
+        };
+
+        if let Some(pyramid) = occlusion.as_deref_mut() {
+            let obb = match node.deformed_verts {
+                Some(_) => node_obb(scene, node),
+                None => scene.obbs[model],
+            };
+            if pyramid.occluded(&view_proj_matrix, width, height, obb, node.world_matrix) {
+                continue;
+            }
+        }
+
+        if node.material.is_none() && node.deformed_verts.is_none() {
+            instances[model].push(node.world_matrix);
+            continue;
+        }
+
+        draw_node(
+            scene,
+            node,
+            model,
+            frame_buffer,
+            zbuffer,
+            camera,
+            width,
+            height,
+            &mut pass,
+        );
+    }
+
+    for (model, instances) in instances.iter().enumerate() {
+        if instances.is_empty() {
+            continue;
+        }
+
+        model::draw_model_instanced(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            camera,
+            &scene.models[model],
+            scene.obbs[model],
+            instances,
+            None,
+            Some(&mut pass),
+        );
+    }
+
+    pass.flush(frame_buffer, zbuffer, width, height);
+}
+
+fn draw_node<'a>(
+    scene: &'a Scene,
+    node: &'a Node,
+    model: usize,
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    pass: &mut model::DrawPass<'a>,
+) {
+    let obb = match node.deformed_verts {
+        Some(_) => node_obb(scene, node),
+        None => scene.obbs[model],
+    };
+    if !model::obb_visible_matrix(width, height, camera, obb, node.world_matrix) {
+        return;
+    }
+
+    let model = &scene.models[model];
+    model::draw_model_matrix_deformed(
+        frame_buffer,
+        zbuffer,
+        width,
+        height,
+        camera,
+        model,
+        node.deformed_verts.as_ref().unwrap_or(&model.verts),
+        node.world_matrix,
+        node.material.map(|material| &scene.materials[material]),
+        Some(pass),
+    );
+}
+
+// The hierarchy over the node's vertices, deformed or not. The node must have a
+// model.
+fn node_bvh<'a>(scene: &'a Scene, node: &'a Node) -> &'a Bvh {
+    debug_assert_eq!(
+        node.deformed_verts.is_some(),
+        node.deformed_bvh.is_some(),
+        "call `Scene::refit_deformed` after changing `deformed_verts`"
+    );
+    match &node.deformed_bvh {
+        Some((_, bvh)) => bvh,
+        None => &scene.bvhs[node.model.expect("node has no model")],
+    }
+}
+
+fn node_obb(scene: &Scene, node: &Node) -> model::Obb {
+    debug_assert_eq!(
+        node.deformed_verts.is_some(),
+        node.deformed_obb.is_some(),
+        "call `Scene::refit_deformed` after changing `deformed_verts`"
+    );
+    node.deformed_obb
+        .unwrap_or_else(|| scene.obbs[node.model.expect("node has no model")])
+}
+
+/// Renders every visible model in the scene into `shadow_map`. The shadow map
+/// should already be cleared with [`ShadowMap::begin`].
+pub fn render_shadow_map(scene: &Scene, shadow_map: &mut ShadowMap) {
+    for node in scene.nodes.iter() {
+        let Some(model) = node.model else {
+            continue;
+        };
+        if node.world_visible {
+            let model = &scene.models[model];
+            shadow_map.draw_model_deformed(
+                model,
+                node.deformed_verts.as_ref().unwrap_or(&model.verts),
+                node.world_matrix,
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub node: NodeId,
+    // Index into `Scene::models`.
+    pub model: usize,
+    // Index of the face in the model.
+    pub triangle: usize,
+    // Weights of the face's vertices at the hit.
+    pub barycentrics: Vec3,
+    pub uv: Option<Vec2>,
+    // Distance along the ray.
+    pub t: f32,
+    pub position: Vec3,
+    // World space face normal, on whichever side of the face it was wound.
+    pub normal: Vec3,
+}
+
+/// The nearest visible face hit by `ray`, e.g. from
+/// [`screen_to_world_ray`] for the pixel under the mouse. Nodes are tested
+/// against the full detail model, whatever level of detail was drawn, and
+/// deformed nodes against the level they were deformed from.
+pub fn pick(scene: &Scene, ray: Ray) -> Option<Hit> {
+    intersect(scene, ray, f32::INFINITY)
+}
+
+/// The nearest visible face hit by `ray` closer than `max_t`.
+pub fn intersect(scene: &Scene, ray: Ray, max_t: f32) -> Option<Hit> {
+    let mut nearest: Option<Hit> = None;
+
+    for (index, node) in scene.nodes.iter().enumerate() {
+        let Some(model_index) = node.model else {
+            continue;
+        };
+        if !node.world_visible {
+            continue;
+        }
+
+        // Tested in model space, where distances along the ray are unchanged.
+        let inverse = node.world_matrix.inverse_affine();
+        let local = ray.transform(&inverse);
+        let max_t = nearest.map_or(max_t, |hit| hit.t);
+
+        let model = &scene.models[model_index];
+        let verts = node.deformed_verts.as_ref().unwrap_or(&model.verts);
+        let hit = node_bvh(scene, node).closest_hit(&model.faces, verts, &local, max_t);
+        let Some((triangle, t, barycentrics)) = hit else {
+            continue;
+        };
+
+        let uv = model::face_uvs(model, triangle).map(|(uv1, uv2, uv3)| {
+            uv1 * barycentrics.x + uv2 * barycentrics.y + uv3 * barycentrics.z
+        });
+        let face = &model.faces[triangle * 3..triangle * 3 + 3];
+        let normal = (verts[face[1]] - verts[face[0]]).cross(verts[face[2]] - verts[face[0]]);
+        // Normals transform by the inverse transpose.
+        let normal = inverse
+            .transpose()
+            .mult_vec4(normal.extend(0.0))
+            .reduce()
+            .normalize_or_zero();
+        nearest = Some(Hit {
+            node: NodeId(index),
+            model: model_index,
+            triangle,
+            barycentrics,
+            uv,
+            t,
+            position: ray.at(t),
+            normal,
+        });
+    }
+
+    nearest
+}
+
+/// Whether any visible face is hit by `ray` closer than `max_t`, e.g. between a
+/// surface and a light.
+pub fn occluded(scene: &Scene, ray: Ray, max_t: f32) -> bool {
+    scene.nodes.iter().any(|node| {
+        let Some(model_index) = node.model.filter(|_| node.world_visible) else {
+            return false;
+        };
+        let local = ray.transform(&node.world_matrix.inverse_affine());
+        let model = &scene.models[model_index];
+        let verts = node.deformed_verts.as_ref().unwrap_or(&model.verts);
+        node_bvh(scene, node).any_hit(&model.faces, verts, &local, max_t)
+    })
+}
@@ -0,0 +1,172 @@
+use crate::{camera::Camera, math::*, model::Model};
+use rast::tint::*;
+
+#[derive(Debug, Clone, Copy)]
+pub enum LightKind {
+    // Orthographic projection covering `half_extent` on each side of the light.
+    Directional { half_extent: f32 },
+    Spot { fov: f32 },
+}
+
+// Lights are positioned and aimed like a `Camera`.
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub translation: Vec3,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub nearz: f32,
+    pub farz: f32,
+}
+
+impl Light {
+    pub fn compute_view_proj_matrix(&self, width: usize, height: usize) -> Mat4 {
+        let view_matrix = compute_view_matrix(self.translation, self.yaw, self.pitch);
+        let proj_matrix = match self.kind {
+            LightKind::Directional { half_extent } => compute_orthographic_proj_matrix(
+                -half_extent,
+                half_extent,
+                -half_extent,
+                half_extent,
+                self.nearz,
+                self.farz,
+            ),
+            LightKind::Spot { fov } => compute_perspective_proj_matrix(
+                &Camera {
+                    translation: self.translation,
+                    pitch: self.pitch,
+                    yaw: self.yaw,
+                    fov,
+                    nearz: self.nearz,
+                    farz: self.farz,
+                },
+                width,
+                height,
+            ),
+        };
+        proj_matrix.mult_mat4(&view_matrix)
+    }
+}
+
+pub struct ShadowMap {
+    pub width: usize,
+    pub height: usize,
+    pub depth: Vec<f32>,
+    // Subtracted from a fragment's depth before it is compared with the map.
+    pub bias: f32,
+    // Percentage closer filtering kernel radius in texels. A radius of 1 samples
+    // a 3x3 grid.
+    pub pcf_radius: usize,
+    // How much a fully shadowed fragment is darkened.
+    pub strength: f32,
+
+    view_proj_matrix: Mat4,
+    // `rast` always writes color, so depth is rendered alongside a throwaway
+    // color target.
+    scratch: Vec<Srgb>,
+}
+
+impl ShadowMap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            depth: vec![1.0; width * height],
+            bias: 0.005,
+            pcf_radius: 1,
+            strength: 0.6,
+            view_proj_matrix: Mat4::IDENTITY,
+            scratch: vec![Srgb::from_rgb(0, 0, 0); width * height],
+        }
+    }
+
+    /// Clears the map and renders subsequent models from `light`.
+    pub fn begin(&mut self, light: &Light) {
+        self.view_proj_matrix = light.compute_view_proj_matrix(self.width, self.height);
+        self.depth.fill(1.0);
+    }
+
+    pub fn draw_model(&mut self, model: &Model, model_matrix: Mat4) {
+        self.draw_model_deformed(model, &model.verts, model_matrix);
+    }
+
+    /// Draws `model` with `verts` in place of the model's own vertices.
+    pub fn draw_model_deformed(&mut self, model: &Model, verts: &[Vec3], model_matrix: Mat4) {
+        debug_assert_eq!(verts.len(), model.verts.len());
+        let matrix = self.view_proj_matrix.mult_mat4(&model_matrix);
+        for face in model.faces.chunks(3) {
+            let v1 = self.clip_to_texel(matrix.mult_vec4(verts[face[0]].extend(1.0)));
+            let v2 = self.clip_to_texel(matrix.mult_vec4(verts[face[1]].extend(1.0)));
+            let v3 = self.clip_to_texel(matrix.mult_vec4(verts[face[2]].extend(1.0)));
+            let (Some(v1), Some(v2), Some(v3)) = (v1, v2, v3) else {
+                continue;
+            };
+
+            rast::rast_triangle_colored_checked(
+                &mut self.scratch,
+                &mut self.depth,
+                self.width,
+                self.height,
+                libm::floorf(v1.x) as i32,
+                libm::floorf(v1.y) as i32,
+                v1.z,
+                libm::floorf(v2.x) as i32,
+                libm::floorf(v2.y) as i32,
+                v2.z,
+                libm::floorf(v3.x) as i32,
+                libm::floorf(v3.y) as i32,
+                v3.z,
+                Srgb::from_rgb(0, 0, 0),
+            );
+        }
+    }
+
+    /// Fraction of the PCF kernel around `world` that is lit. Points outside of
+    /// the map are fully lit.
+    pub fn visibility(&self, world: Vec3) -> f32 {
+        let v = self.view_proj_matrix.mult_vec4(world.extend(1.0));
+        let Some(v) = self.clip_to_texel(v) else {
+            return 1.0;
+        };
+
+        let x = libm::floorf(v.x) as isize;
+        let y = libm::floorf(v.y) as isize;
+        let radius = self.pcf_radius as isize;
+        let mut lit = 0;
+        let mut total = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let sx = x + dx;
+                let sy = y + dy;
+                total += 1;
+                if sx < 0 || sy < 0 || sx >= self.width as isize || sy >= self.height as isize {
+                    lit += 1;
+                    continue;
+                }
+                if v.z - self.bias <= self.depth[sy as usize * self.width + sx as usize] {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / total as f32
+    }
+
+    /// Brightness multiplier for a fragment at `world`.
+    pub fn light_factor(&self, world: Vec3) -> f32 {
+        1.0 - self.strength * (1.0 - self.visibility(world))
+    }
+
+    fn clip_to_texel(&self, v: Vec4) -> Option<Vec3> {
+        if v.w <= 0.0 {
+            return None;
+        }
+        let ndc = v.reduce() / v.w;
+        (ndc.z >= 0.0 && ndc.z <= 1.0).then(|| {
+            Vec3::new(
+                (ndc.x + 1.0) / 2.0 * self.width as f32,
+                (1.0 - (ndc.y + 1.0) / 2.0) * self.height as f32,
+                ndc.z,
+            )
+        })
+    }
+}
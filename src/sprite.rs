@@ -0,0 +1,229 @@
+use crate::{
+    camera::Camera,
+    fog::{self, DepthConvention},
+    hiz,
+    math::*,
+    raster,
+};
+use rast::tint::*;
+
+// Camera facing quads at world positions, e.g. for particles, markers and
+// distant objects.
+
+#[derive(Debug, Clone, Copy)]
+pub enum SpriteSize {
+    // Width and height in world units, so the sprite shrinks with distance.
+    World(Vec2),
+    // Width and height in pixels at any distance.
+    Pixels(Vec2),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Billboard {
+    // Faces the camera from every direction.
+    Spherical,
+    // Only turns around the world space axis, which stays the sprite's up, e.g.
+    // for trees and flames.
+    Cylindrical(Vec3),
+}
+
+#[derive(Clone, Copy)]
+pub struct Sprite<'a> {
+    // World space center.
+    pub position: Vec3,
+    pub size: SpriteSize,
+    pub billboard: Billboard,
+    // Multiplies the texture, or fills the sprite if there is no texture.
+    pub color: Srgb,
+    pub texture: Option<&'a (usize, usize, Vec<Srgb>)>,
+    // Multiplies the alpha of the color and texture.
+    pub opacity: f32,
+    // Hides the sprite behind what is already in the zbuffer, and lets opaque
+    // fragments write their depth. Without it, the sprite is drawn over
+    // everything and leaves the zbuffer as it was.
+    pub depth_test: bool,
+}
+
+impl<'a> Sprite<'a> {
+    pub fn colored(position: Vec3, size: SpriteSize, color: Srgb) -> Self {
+        Self {
+            position,
+            size,
+            billboard: Billboard::Spherical,
+            color,
+            texture: None,
+            opacity: 1.0,
+            depth_test: true,
+        }
+    }
+
+    pub fn textured(
+        position: Vec3,
+        size: SpriteSize,
+        texture: &'a (usize, usize, Vec<Srgb>),
+    ) -> Self {
+        Self {
+            texture: Some(texture),
+            ..Self::colored(position, size, Srgb::from_rgb(255, 255, 255))
+        }
+    }
+}
+
+/// Draws `sprites` farthest first, so that they blend over each other. `zbuffer`
+/// must hold depth in `convention`.
+pub fn draw_sprites(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    convention: DepthConvention,
+    sprites: &mut [Sprite],
+) {
+    let distance = |sprite: &Sprite| sprite.position.distance_squared(camera.translation);
+    sprites.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+    for sprite in sprites.iter() {
+        draw_sprite(
+            frame_buffer,
+            zbuffer,
+            width,
+            height,
+            camera,
+            convention,
+            sprite,
+        );
+    }
+}
+
+/// Draws a single sprite. Sprites that cross the near plane are skipped.
+pub fn draw_sprite(
+    frame_buffer: &mut [Srgb],
+    zbuffer: &mut [f32],
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    convention: DepthConvention,
+    sprite: &Sprite,
+) {
+    debug_assert_eq!(frame_buffer.len(), zbuffer.len());
+
+    let view_depth = vertex_world_to_camera_space(camera, sprite.position).z;
+    if view_depth <= camera.nearz {
+        return;
+    }
+
+    let half_size = match sprite.size {
+        SpriteSize::World(size) => size / 2.0,
+        SpriteSize::Pixels(size) => {
+            // World units covered by a pixel at the sprite's depth.
+            let pixel = 2.0 * view_depth * libm::tanf(camera.fov / 2.0) / height as f32;
+            size * pixel / 2.0
+        }
+    };
+    let (right, up) = match sprite.billboard {
+        Billboard::Spherical => (
+            Vec3::x(1.0).rotate_x(camera.pitch).rotate_y(camera.yaw),
+            Vec3::y(1.0).rotate_x(camera.pitch).rotate_y(camera.yaw),
+        ),
+        Billboard::Cylindrical(axis) => {
+            let up = axis.normalize();
+            let right = (camera.translation - sprite.position)
+                .cross(up)
+                .normalize_or_zero();
+            if right == Vec3::ZERO {
+                // Looking straight along the axis, where the sprite is edge on.
+                return;
+            }
+            (right, up)
+        }
+    };
+
+    // Top left, top right, bottom right, bottom left.
+    let corners = [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)]
+        .map(|(x, y)| sprite.position + right * (x * half_size.x) + up * (y * half_size.y));
+    let uvs = [
+        Vec2::new(0.0, 1.0),
+        Vec2::new(1.0, 1.0),
+        Vec2::new(1.0, 0.0),
+        Vec2::new(0.0, 0.0),
+    ];
+
+    let view_proj_matrix = hiz::view_proj_matrix(camera, width, height);
+    let mut screen = [Vec3::ZERO; 4];
+    for (screen, corner) in screen.iter_mut().zip(corners) {
+        *screen = match project(width, height, camera, convention, &view_proj_matrix, corner) {
+            Some(v) => v,
+            None => return,
+        };
+    }
+    // View space depth of each corner, to interpolate uvs perspective correct.
+    let depths = screen.map(|v| fog::linearize_depth(v.z, convention, camera));
+
+    for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+        raster::rast_triangle_fragments(
+            width,
+            height,
+            screen[a],
+            screen[b],
+            screen[c],
+            |x, y, z, weights| {
+                let index = y * width + x;
+                if sprite.depth_test && z >= zbuffer[index] {
+                    return;
+                }
+
+                let color = match sprite.texture {
+                    Some(texture) => {
+                        let w = Vec3::new(
+                            weights.x / depths[a],
+                            weights.y / depths[b],
+                            weights.z / depths[c],
+                        );
+                        let uv = (uvs[a] * w.x + uvs[b] * w.y + uvs[c] * w.z) / (w.x + w.y + w.z);
+                        multiply(raster::sample_texture(texture, uv, true), sprite.color)
+                    }
+                    None => sprite.color,
+                };
+
+                let alpha = color.a as f32 / 255.0 * sprite.opacity;
+                if alpha <= 0.0 {
+                    return;
+                }
+                frame_buffer[index] = raster::blend_srgb(frame_buffer[index], color, alpha);
+                if sprite.depth_test && alpha >= 1.0 {
+                    zbuffer[index] = z;
+                }
+            },
+        );
+    }
+}
+
+// Screen position and zbuffer depth of `v` in `convention`.
+fn project(
+    width: usize,
+    height: usize,
+    camera: &Camera,
+    convention: DepthConvention,
+    view_proj_matrix: &Mat4,
+    v: Vec3,
+) -> Option<Vec3> {
+    match convention {
+        DepthConvention::Linear => vertex_world_to_screen_space_clipped(width, height, camera, v),
+        DepthConvention::Perspective => {
+            let v = view_proj_matrix.mult_vec4(v.extend(1.0));
+            if v.w <= 0.0 || v.z < 0.0 || v.z > v.w {
+                return None;
+            }
+            Some(Vec3::new(
+                (v.x / v.w + 1.0) / 2.0 * width as f32,
+                (1.0 - v.y / v.w) / 2.0 * height as f32,
+                v.z / v.w,
+            ))
+        }
+    }
+}
+
+fn multiply(a: Srgb, b: Srgb) -> Srgb {
+    let mul = |a: u8, b: u8| ((a as u16 * b as u16) / 255) as u8;
+    Srgb::new(mul(a.r, b.r), mul(a.g, b.g), mul(a.b, b.b), mul(a.a, b.a))
+}
@@ -0,0 +1,28 @@
+use rast::tint::*;
+
+// Color and depth buffers of the same size, in the row major layout the draw
+// functions expect. Depth is cleared to 1, the far plane.
+#[derive(Default)]
+pub struct RenderTarget {
+    pub width: usize,
+    pub height: usize,
+    pub color: Vec<Srgb>,
+    pub depth: Vec<f32>,
+}
+
+impl RenderTarget {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut target = Self::default();
+        target.resize(width, height);
+        target
+    }
+
+    /// Resizes both buffers, keeping their allocations when shrinking. The
+    /// contents are undefined after a resize until the next clear.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.color.resize(width * height, Srgb::from_rgb(0, 0, 0));
+        self.depth.resize(width * height, 1.0);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{math::*, raster};
+use rast::tint::*;
+use std::collections::HashMap;
+
+// Bitmap font text, drawn straight into the frame buffer for the HUD and debug
+// labels.
+//
+// Glyph coverage is the alpha of the page texel times its brightest channel, so
+// both white glyphs on a transparent page and opaque grayscale pages work. The
+// text is tinted with a single color.
+pub struct Font {
+    // Distance between the tops of two lines, in texels.
+    pub line_height: f32,
+    pub glyphs: HashMap<char, Glyph>,
+    // Added to the advance between two characters, in texels.
+    pub kerning: HashMap<(char, char), f32>,
+    pub pages: Vec<(usize, usize, Vec<Srgb>)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph {
+    pub page: usize,
+    // Rectangle in the page, in texels.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    // From the pen, at the top of the line, to the top left of the rectangle.
+    pub offset: Vec2,
+    // Moves the pen to the next character.
+    pub advance: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    // Lines start at the x position.
+    #[default]
+    Left,
+    // Lines are centered on the x position.
+    Center,
+    // Lines end at the x position.
+    Right,
+}
+
+// The embedded font covers printable ASCII with 5x7 glyphs in 6x10 cells.
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const CELL_WIDTH: usize = 6;
+const LINE_HEIGHT: usize = 10;
+
+impl Default for Font {
+    /// The embedded font.
+    fn default() -> Self {
+        const COLUMNS: usize = 16;
+        let rows = DEFAULT_GLYPHS.len().div_ceil(COLUMNS);
+        let (width, height) = (COLUMNS * GLYPH_WIDTH, rows * GLYPH_HEIGHT);
+        let mut page = vec![Srgb::new(255, 255, 255, 0); width * height];
+
+        let mut glyphs = HashMap::new();
+        for (i, bitmap) in DEFAULT_GLYPHS.iter().enumerate() {
+            let (x, y) = (i % COLUMNS * GLYPH_WIDTH, i / COLUMNS * GLYPH_HEIGHT);
+            for (row, bits) in bitmap.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if (bits >> (GLYPH_WIDTH - 1 - column)) & 1 == 1 {
+                        page[(y + row) * width + x + column].a = 255;
+                    }
+                }
+            }
+            glyphs.insert(
+                char::from(b' ' + i as u8),
+                Glyph {
+                    page: 0,
+                    x,
+                    y,
+                    width: GLYPH_WIDTH,
+                    height: GLYPH_HEIGHT,
+                    offset: Vec2::new(0.0, 1.0),
+                    advance: CELL_WIDTH as f32,
+                },
+            );
+        }
+
+        Self {
+            line_height: LINE_HEIGHT as f32,
+            glyphs,
+            kerning: HashMap::new(),
+            pages: vec![(width, height, page)],
+        }
+    }
+}
+
+impl Font {
+    /// The glyph for `c`, or for `?` if the font doesn't have it.
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    /// Size in pixels of `text` drawn at `scale`.
+    pub fn measure(&self, text: &str, scale: f32) -> Vec2 {
+        let width = text
+            .lines()
+            .map(|line| self.line_width(line))
+            .fold(0.0, f32::max);
+        Vec2::new(width, text.lines().count() as f32 * self.line_height) * scale
+    }
+
+    // Sum of the advances and kerning of `line`, in texels.
+    fn line_width(&self, line: &str) -> f32 {
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            width += self.kerning_between(previous, c);
+            width += self.glyph(c).map_or(0.0, |glyph| glyph.advance);
+            previous = Some(c);
+        }
+        width
+    }
+
+    fn kerning_between(&self, previous: Option<char>, c: char) -> f32 {
+        previous
+            .and_then(|previous| self.kerning.get(&(previous, c)))
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
+
+/// Draws `text` with the top of its first line at `y` and every line aligned to
+/// `x`. `scale` multiplies the size in texels, whole numbers keep the glyphs
+/// crisp.
+pub fn draw_text(
+    frame_buffer: &mut [Srgb],
+    width: usize,
+    height: usize,
+    font: &Font,
+    text: &str,
+    x: f32,
+    y: f32,
+    color: Srgb,
+    scale: f32,
+    align: Align,
+) {
+    debug_assert_eq!(frame_buffer.len(), width * height);
+
+    for (i, line) in text.lines().enumerate() {
+        let line_width = font.line_width(line) * scale;
+        let mut pen = Vec2::new(
+            match align {
+                Align::Left => x,
+                Align::Center => x - line_width / 2.0,
+                Align::Right => x - line_width,
+            },
+            y + i as f32 * font.line_height * scale,
+        );
+
+        let mut previous = None;
+        for c in line.chars() {
+            pen.x += font.kerning_between(previous, c) * scale;
+            previous = Some(c);
+            let Some(glyph) = font.glyph(c) else {
+                continue;
+            };
+            draw_glyph(
+                frame_buffer,
+                width,
+                height,
+                &font.pages[glyph.page],
+                glyph,
+                pen + glyph.offset * scale,
+                color,
+                scale,
+            );
+            pen.x += glyph.advance * scale;
+        }
+    }
+}
+
+// Scales the glyph with nearest sampling, which suits pixel fonts.
+fn draw_glyph(
+    frame_buffer: &mut [Srgb],
+    width: usize,
+    height: usize,
+    page: &(usize, usize, Vec<Srgb>),
+    glyph: &Glyph,
+    position: Vec2,
+    color: Srgb,
+    scale: f32,
+) {
+    let (page_width, _, texels) = page;
+    let x0 = libm::roundf(position.x) as i32;
+    let y0 = libm::roundf(position.y) as i32;
+    let glyph_width = libm::ceilf(glyph.width as f32 * scale) as i32;
+    let glyph_height = libm::ceilf(glyph.height as f32 * scale) as i32;
+    let texel = |v: i32, size: usize| (((v as f32 + 0.5) / scale) as usize).min(size - 1);
+
+    for dy in 0..glyph_height {
+        let y = y0 + dy;
+        if y < 0 || y >= height as i32 {
+            continue;
+        }
+        let ty = glyph.y + texel(dy, glyph.height);
+        for dx in 0..glyph_width {
+            let x = x0 + dx;
+            if x < 0 || x >= width as i32 {
+                continue;
+            }
+            let tx = glyph.x + texel(dx, glyph.width);
+
+            let t = texels[ty * page_width + tx];
+            let coverage = t.a as f32 / 255.0 * t.r.max(t.g).max(t.b) as f32 / 255.0;
+            let alpha = coverage * color.a as f32 / 255.0;
+            if alpha > 0.0 {
+                let index = y as usize * width + x as usize;
+                frame_buffer[index] = raster::blend_srgb(frame_buffer[index], color, alpha);
+            }
+        }
+    }
+}
+
+// Rows of the embedded glyphs from ' ' to '~', top to bottom, with the leftmost
+// column in the highest of the 5 bits.
+#[rustfmt::skip]
+const DEFAULT_GLYPHS: [[u8; GLYPH_HEIGHT]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00], [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a],
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], [0x04, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00],
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08],
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e],
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f], [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1e], [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e],
+    [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f], [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e], [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08], [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e],
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11], [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e], [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0c], [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12],
+    [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11], [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11], [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e],
+    [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10], [0x00, 0x00, 0x0d, 0x13, 0x0f, 0x01, 0x01], [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10], [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e],
+    [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06], [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0d], [0x00, 0x00, 0x11, 0x11, 0x11, 0x0a, 0x04], [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0a],
+    [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11], [0x00, 0x00, 0x11, 0x11, 0x0f, 0x01, 0x0e], [0x00, 0x00, 0x1f, 0x02, 0x04, 0x08, 0x1f], [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02],
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08], [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00],
+];
@@ -0,0 +1,286 @@
+use crate::{
+    aa,
+    background::{self, Background},
+    camera::Camera,
+    math::*,
+    rng::Rng,
+    scene::{self, Scene},
+    shadow::{Light, LightKind},
+};
+use rast::tint::*;
+
+// Offline renderer that path traces the same `Scene` the rasterizer draws, with
+// the same materials, camera and light, for reference images and lighting
+// comparisons.
+//
+// Every call to `render` adds `samples_per_frame` samples per pixel to a running
+// average, so the image converges while the view is held still. Changing the
+// camera, the settings or the frame size starts over.
+//
+// With `rays_per_frame` set, a call stops after that many camera rays and the
+// next call carries on from the same pixel, so an interactive frame never takes
+// longer than its budget. Pixels that have not been reached by the first sample
+// are black.
+pub struct Tracer {
+    // Diffuse bounces after the first hit. 0 only lights surfaces directly, with
+    // `ambient` standing in for the indirect light.
+    pub bounces: usize,
+    pub samples_per_frame: usize,
+    // Caps the camera rays traced by each call to `render`.
+    pub rays_per_frame: Option<usize>,
+    pub ambient: f32,
+    pub light_intensity: f32,
+
+    accumulation: Vec<[f32; 3]>,
+    samples: usize,
+    // Next pixel of the sample in progress.
+    cursor: usize,
+    // What the accumulated samples were traced with.
+    key: Option<[f32; 12]>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self {
+            bounces: 2,
+            samples_per_frame: 1,
+            rays_per_frame: None,
+            ambient: 0.2,
+            light_intensity: 1.0,
+            accumulation: Vec::new(),
+            samples: 0,
+            cursor: 0,
+            key: None,
+        }
+    }
+}
+
+// Transparent faces are passed through at random in proportion to their alpha,
+// at most this many times per path segment.
+const MAX_TRANSPARENT_LAYERS: usize = 8;
+// Keeps rays from hitting the surface they start on.
+const RAY_OFFSET: f32 = 1e-3;
+
+impl Tracer {
+    /// Discards the accumulated samples.
+    pub fn reset(&mut self) {
+        self.samples = 0;
+        self.cursor = 0;
+        self.accumulation.fill([0.0; 3]);
+    }
+
+    /// Number of samples averaged into every pixel so far. Pixels before the
+    /// cursor of a partly traced sample have one more.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Traces another `samples_per_frame` samples per pixel of `scene`, or up to
+    /// `rays_per_frame` camera rays, and writes the average so far into
+    /// `frame_buffer`. Rays that miss the scene see `background`.
+    pub fn render(
+        &mut self,
+        scene: &Scene,
+        light: &Light,
+        background: &Background,
+        frame_buffer: &mut [Srgb],
+        width: usize,
+        height: usize,
+        camera: &Camera,
+    ) {
+        debug_assert_eq!(frame_buffer.len(), width * height);
+
+        let key = [
+            camera.translation.x,
+            camera.translation.y,
+            camera.translation.z,
+            camera.pitch,
+            camera.yaw,
+            camera.fov,
+            width as f32,
+            height as f32,
+            self.bounces as f32,
+            self.samples_per_frame as f32,
+            self.ambient,
+            self.light_intensity,
+        ];
+        if self.key != Some(key) || self.accumulation.len() != width * height {
+            self.key = Some(key);
+            self.accumulation.clear();
+            self.accumulation.resize(width * height, [0.0; 3]);
+            self.samples = 0;
+            self.cursor = 0;
+        }
+
+        let pixels = width * height;
+        let mut rays = self.samples_per_frame.max(1) * pixels;
+        if let Some(budget) = self.rays_per_frame {
+            rays = rays.min(budget.max(1));
+        }
+        for _ in 0..rays {
+            let index = self.cursor;
+            let mut rng = Rng::new(index as u32, self.samples as u32);
+            let ray = screen_to_world_ray(
+                width,
+                height,
+                camera,
+                (index % width) as f32 + rng.next_f32(),
+                (index / width) as f32 + rng.next_f32(),
+            );
+            let radiance = self.trace_path(scene, light, background, ray, &mut rng);
+            let sum = &mut self.accumulation[index];
+            for (sum, radiance) in sum.iter_mut().zip(radiance) {
+                *sum += radiance;
+            }
+
+            self.cursor += 1;
+            if self.cursor == pixels {
+                self.cursor = 0;
+                self.samples += 1;
+            }
+        }
+
+        for (index, (pixel, sum)) in frame_buffer
+            .iter_mut()
+            .zip(self.accumulation.iter())
+            .enumerate()
+        {
+            let samples = self.samples + (index < self.cursor) as usize;
+            let scale = if samples > 0 {
+                1.0 / samples as f32
+            } else {
+                0.0
+            };
+            let encode = |c: f32| {
+                libm::roundf(aa::linear_to_srgb((c * scale).clamp(0.0, 1.0)) * 255.0) as u8
+            };
+            *pixel = Srgb::from_rgb(encode(sum[0]), encode(sum[1]), encode(sum[2]));
+        }
+    }
+
+    fn trace_path(
+        &self,
+        scene: &Scene,
+        light: &Light,
+        background: &Background,
+        mut ray: Ray,
+        rng: &mut Rng,
+    ) -> [f32; 3] {
+        let mut radiance = [0.0; 3];
+        let mut throughput = [1.0; 3];
+
+        for bounce in 0..=self.bounces {
+            let Some((hit, albedo)) = next_opaque_hit(scene, ray, rng) else {
+                let sky = linear(background::sample_background(
+                    background,
+                    ray.dir.normalize(),
+                ));
+                for c in 0..3 {
+                    radiance[c] += throughput[c] * sky[c];
+                }
+                break;
+            };
+
+            // Faces are lit from whichever side the ray arrived on.
+            let normal = if hit.normal.dot(ray.dir) > 0.0 {
+                -hit.normal
+            } else {
+                hit.normal
+            };
+            let origin = hit.position + normal * RAY_OFFSET;
+
+            let mut direct = self.direct_light(scene, light, origin, normal);
+            if self.bounces == 0 {
+                direct += self.ambient;
+            }
+            for c in 0..3 {
+                throughput[c] *= albedo[c];
+                radiance[c] += throughput[c] * direct;
+            }
+
+            if bounce == self.bounces {
+                break;
+            }
+            // Cosine weighted directions cancel the cosine term and the pdf of a
+            // diffuse surface, leaving only the albedo in the throughput.
+            ray = Ray::new(origin, cosine_hemisphere(normal, rng));
+        }
+
+        radiance
+    }
+
+    // Light arriving at `position` straight from `light`, or 0 if it is shadowed.
+    fn direct_light(&self, scene: &Scene, light: &Light, position: Vec3, normal: Vec3) -> f32 {
+        let forward = Vec3::z(1.0).rotate_x(light.pitch).rotate_y(light.yaw);
+        let (to_light, distance) = match light.kind {
+            LightKind::Directional { .. } => (-forward, f32::INFINITY),
+            LightKind::Spot { fov } => {
+                let offset = light.translation - position;
+                let distance = offset.length();
+                let to_light = offset / distance;
+                if (-to_light).dot(forward) < libm::cosf(fov / 2.0) {
+                    return 0.0;
+                }
+                (to_light, distance)
+            }
+        };
+
+        let cosine = normal.dot(to_light);
+        if cosine <= 0.0 || scene::occluded(scene, Ray::new(position, to_light), distance) {
+            return 0.0;
+        }
+        cosine * self.light_intensity
+    }
+}
+
+// The nearest hit along `ray` that isn't passed through, and the linear albedo
+// there.
+fn next_opaque_hit(scene: &Scene, mut ray: Ray, rng: &mut Rng) -> Option<(scene::Hit, [f32; 3])> {
+    for _ in 0..MAX_TRANSPARENT_LAYERS {
+        let hit = scene::intersect(scene, ray, f32::INFINITY)?;
+        let model = scene.model(hit.model);
+        let material = scene
+            .node(hit.node)
+            .material
+            .map(|material| &scene.materials[material])
+            .or_else(|| model.face_material(hit.triangle));
+
+        // Faces without a material are white, like `Material::default`.
+        let Some(material) = material else {
+            return Some((hit, [1.0; 3]));
+        };
+        let (color, alpha) = material.shade(hit.uv);
+        let passed = match material.alpha_cutoff {
+            Some(cutoff) => alpha < cutoff,
+            None => material.is_blended() && rng.next_f32() >= alpha,
+        };
+        if !passed {
+            return Some((hit, linear(color)));
+        }
+        ray = Ray::new(hit.position + ray.dir * RAY_OFFSET, ray.dir);
+    }
+    None
+}
+
+fn linear(color: Srgb) -> [f32; 3] {
+    [color.r, color.g, color.b].map(|c| aa::srgb_to_linear(c as f32 / 255.0))
+}
+
+// https://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/2D_Sampling_with_Multidimensional_Transformations#Cosine-WeightedHemisphereSampling
+fn cosine_hemisphere(normal: Vec3, rng: &mut Rng) -> Vec3 {
+    let r = libm::sqrtf(rng.next_f32());
+    let phi = rng.next_f32() * core::f32::consts::TAU;
+    let (sin, cos) = libm::sincosf(phi);
+    let (x, y) = (r * cos, r * sin);
+    let z = libm::sqrtf((1.0 - x * x - y * y).max(0.0));
+
+    // Any basis around the normal will do.
+    let helper = if libm::fabsf(normal.x) > 0.9 {
+        Vec3::y(1.0)
+    } else {
+        Vec3::x(1.0)
+    };
+    let tangent = normal.cross(helper).normalize();
+    let bitangent = normal.cross(tangent);
+    tangent * x + bitangent * y + normal * z
+}